@@ -0,0 +1,31 @@
+#![no_main]
+
+//! Feeds arbitrary bytes to the lobby framing. Run with
+//! `cargo fuzz run lobby_packet` from the repository root.
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::{Decoder, Encoder};
+
+#[allow(dead_code)]
+#[path = "../../src/packets/mod.rs"]
+mod packets;
+#[allow(dead_code)]
+#[path = "../../src/validation.rs"]
+mod validation;
+
+use packets::lobby::LobbyCodec;
+
+fuzz_target!(|data: &[u8]| {
+    let mut src = BytesMut::from(data);
+
+    while let Ok(Some(packet)) = LobbyCodec.decode(&mut src) {
+        let mut bytes = BytesMut::new();
+        LobbyCodec.encode(packet.clone(), &mut bytes).unwrap();
+
+        // Only sealed packets are decoded, so sealing again changes nothing.
+        assert_eq!(bytes[..], packet[..]);
+        let decoded = LobbyCodec.decode(&mut bytes).unwrap().unwrap();
+        assert_eq!(decoded, packet);
+    }
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+//! Feeds arbitrary bytes to the auth port codec. Run with
+//! `cargo fuzz run login_packet` from the repository root.
+
+use libfuzzer_sys::fuzz_target;
+
+// The server is a binary, so the modules the codec needs are pulled in by
+// path.
+#[allow(dead_code)]
+#[path = "../../src/packets/mod.rs"]
+mod packets;
+#[allow(dead_code)]
+#[path = "../../src/validation.rs"]
+mod validation;
+
+use packets::login::{LoginReply, LoginRequest};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = LoginRequest::decode(data) {
+        let bytes = request.encode().expect("decoded requests encode");
+        assert_eq!(LoginRequest::decode(&bytes), Ok(request));
+    }
+
+    if let Ok(reply) = LoginReply::decode(data) {
+        assert_eq!(LoginReply::decode(&reply.encode()), Ok(reply));
+    }
+});
@@ -0,0 +1,194 @@
+-- Tables that the login server reads or writes, with the columns it uses.
+-- A full game database still comes from the LandSandBoat SQL files, so
+-- tables that already exist are left as they are.
+--
+-- DEVELOPMENT ONLY: `chars`, the `char_*` tables, `zone_settings`,
+-- `npc_list` and `mob_pools` belong to the map server, and are created here
+-- as partial stubs with only the columns the login server uses. On a
+-- database the map server is going to use, import the LandSandBoat SQL
+-- files before running `migrate`, as the imports skip tables that already
+-- exist and would leave the stubs in place.
+
+CREATE TABLE IF NOT EXISTS `accounts` (
+  `id` int(10) unsigned NOT NULL,
+  `login` varchar(16) NOT NULL,
+  `password` varchar(64) NOT NULL,
+  `timecreate` datetime NOT NULL,
+  `timelastmodify` timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+    ON UPDATE CURRENT_TIMESTAMP,
+  `status` tinyint(3) unsigned NOT NULL DEFAULT 1,
+  `priv` tinyint(3) unsigned NOT NULL DEFAULT 1,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `login` (`login`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `accounts_banned` (
+  `accid` int(10) unsigned NOT NULL,
+  `timebann` datetime NOT NULL,
+  `timeunbann` datetime NOT NULL,
+  `banncomment` varchar(512) DEFAULT NULL,
+  PRIMARY KEY (`accid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `accounts_sessions` (
+  `accid` int(10) unsigned NOT NULL,
+  `charid` int(10) unsigned NOT NULL,
+  `session_key` binary(20) NOT NULL,
+  `server_addr` int(10) unsigned NOT NULL DEFAULT 0,
+  `server_port` smallint(5) unsigned NOT NULL DEFAULT 0,
+  `client_addr` int(10) unsigned NOT NULL DEFAULT 0,
+  `version_mismatch` tinyint(1) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`),
+  UNIQUE KEY `accid` (`accid`),
+  KEY `client_addr` (`client_addr`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `chars` (
+  `charid` int(10) unsigned NOT NULL,
+  `accid` int(10) unsigned NOT NULL,
+  `charname` varchar(15) NOT NULL,
+  `nation` tinyint(1) unsigned NOT NULL DEFAULT 0,
+  `pos_zone` smallint(3) unsigned NOT NULL DEFAULT 0,
+  `pos_prevzone` smallint(3) unsigned NOT NULL DEFAULT 0,
+  `keyitems` blob DEFAULT NULL,
+  `gmlevel` smallint(3) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`),
+  UNIQUE KEY `charname` (`charname`),
+  KEY `accid` (`accid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_look` (
+  `charid` int(10) unsigned NOT NULL,
+  `face` tinyint(3) unsigned NOT NULL DEFAULT 0,
+  `race` tinyint(3) unsigned NOT NULL DEFAULT 0,
+  `size` tinyint(3) unsigned NOT NULL DEFAULT 0,
+  `head` smallint(5) unsigned NOT NULL DEFAULT 0,
+  `body` smallint(5) unsigned NOT NULL DEFAULT 0,
+  `hands` smallint(5) unsigned NOT NULL DEFAULT 0,
+  `legs` smallint(5) unsigned NOT NULL DEFAULT 0,
+  `feet` smallint(5) unsigned NOT NULL DEFAULT 0,
+  `main` smallint(5) unsigned NOT NULL DEFAULT 0,
+  `sub` smallint(5) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_stats` (
+  `charid` int(10) unsigned NOT NULL,
+  `mjob` tinyint(2) unsigned NOT NULL DEFAULT 1,
+  `mlvl` tinyint(2) unsigned NOT NULL DEFAULT 1,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_jobs` (
+  `charid` int(10) unsigned NOT NULL,
+  `unlocked` int(10) unsigned NOT NULL DEFAULT 0,
+  `war` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  `mnk` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  `whm` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  `blm` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  `rdm` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  `thf` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_exp` (
+  `charid` int(10) unsigned NOT NULL,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_points` (
+  `charid` int(10) unsigned NOT NULL,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_profile` (
+  `charid` int(10) unsigned NOT NULL,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_unlocks` (
+  `charid` int(10) unsigned NOT NULL,
+  `outpost_sandy` int(10) unsigned NOT NULL DEFAULT 0,
+  `outpost_bastok` int(10) unsigned NOT NULL DEFAULT 0,
+  `outpost_windy` int(10) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_storage` (
+  `charid` int(10) unsigned NOT NULL,
+  `inventory` tinyint(2) unsigned NOT NULL DEFAULT 30,
+  `satchel` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_inventory` (
+  `charid` int(10) unsigned NOT NULL,
+  `location` tinyint(1) unsigned NOT NULL DEFAULT 0,
+  `slot` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  `itemId` smallint(5) unsigned NOT NULL,
+  `quantity` int(10) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`, `location`, `slot`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_equip` (
+  `charid` int(10) unsigned NOT NULL,
+  `slotid` tinyint(3) unsigned NOT NULL DEFAULT 0,
+  `equipslotid` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  `containerid` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`, `equipslotid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_vars` (
+  `charid` int(10) unsigned NOT NULL,
+  `varname` varchar(50) NOT NULL,
+  `value` int(11) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`, `varname`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_bazaar_msg` (
+  `charid` int(10) unsigned NOT NULL,
+  `bazaar_message` blob DEFAULT NULL,
+  PRIMARY KEY (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_skills` (
+  `charid` int(10) unsigned NOT NULL,
+  `skillid` tinyint(2) unsigned NOT NULL,
+  `value` smallint(4) unsigned NOT NULL DEFAULT 0,
+  `rank` tinyint(2) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`charid`, `skillid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_titles` (
+  `charid` int(10) unsigned NOT NULL,
+  `titleid` smallint(5) unsigned NOT NULL,
+  PRIMARY KEY (`charid`, `titleid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `char_effects` (
+  `charid` int(10) unsigned NOT NULL,
+  `effectid` smallint(5) unsigned NOT NULL,
+  KEY `charid` (`charid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Game data, empty until it is imported. The lobby looks up zone servers
+-- and the names of mobs and NPCs in these.
+
+CREATE TABLE IF NOT EXISTS `zone_settings` (
+  `zoneid` smallint(3) unsigned NOT NULL,
+  `zoneip` tinytext NOT NULL,
+  `zoneport` smallint(5) unsigned NOT NULL DEFAULT 0,
+  PRIMARY KEY (`zoneid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `npc_list` (
+  `npcid` int(10) unsigned NOT NULL,
+  `name` tinytext DEFAULT NULL,
+  PRIMARY KEY (`npcid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `mob_pools` (
+  `poolid` smallint(5) unsigned NOT NULL,
+  `name` varchar(24) DEFAULT NULL,
+  PRIMARY KEY (`poolid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,19 @@
+-- Deleted characters keep their rows until they are purged from the
+-- console, and can be restored until then.
+--
+-- MySQL has no ADD COLUMN IF NOT EXISTS, so the column is only added when
+-- information_schema does not list it yet.
+
+SET @add_deleted = (
+  SELECT IF(COUNT(*) = 0,
+    'ALTER TABLE `chars` ADD COLUMN `deleted` datetime DEFAULT NULL',
+    'DO 0')
+  FROM information_schema.COLUMNS
+  WHERE TABLE_SCHEMA = DATABASE()
+    AND TABLE_NAME = 'chars'
+    AND COLUMN_NAME = 'deleted'
+);
+
+PREPARE add_deleted FROM @add_deleted;
+EXECUTE add_deleted;
+DEALLOCATE PREPARE add_deleted;
@@ -0,0 +1,13 @@
+-- Login attempts, recorded when `login.LOG_USER_IP` is enabled.
+
+CREATE TABLE IF NOT EXISTS `login_ip_log` (
+  `id` bigint(20) unsigned NOT NULL AUTO_INCREMENT,
+  `login_time` datetime NOT NULL,
+  `accid` int(10) unsigned DEFAULT NULL,
+  `login` varchar(16) NOT NULL,
+  `client_ip` varchar(39) NOT NULL,
+  `success` tinyint(1) NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `accid` (`accid`),
+  KEY `client_ip` (`client_ip`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
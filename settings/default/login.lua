@@ -34,6 +34,22 @@ xi.settings.login =
     -- Allow character deletion through the lobby (true/false)
     CHARACTER_DELETION = true,
 
+    -- What to do when an account logs in while one of its characters is still online
+    -- "refuse"  - reject the new login (default)
+    -- "release" - remove the existing session from accounts_sessions and let the new login through.
+    --             The character in game is not disconnected, the map server only drops it once it
+    --             looks the session up again, so the account can be online twice until then.
+    -- "allow"   - let the new login through and leave the existing session alone
+    EXISTING_SESSION = "refuse",
+
+    -- Run OPTIMIZE TABLE over the account and character tables at startup (true/false)
+    -- Each table is locked while it is optimized, which can take a while on large databases.
+    -- The admin console can also run it at any time with `optimize`.
+    OPTIMIZE_TABLES = false,
+
+    -- Hours between runs of OPTIMIZE_TABLES after the one at startup (0 to only run at startup)
+    OPTIMIZE_TABLES_INTERVAL = 0,
+
     -- Number of simultaneous game sessions per IP (0 for no limit)
     LOGIN_LIMIT = 0,
 
@@ -25,6 +25,8 @@ xi.settings.network =
     LOGIN_AUTH_PORT = 54231,
     LOGIN_CONF_IP   = "0.0.0.0",
     LOGIN_CONF_PORT = 51220,
+    -- The login IPs may also be IPv6 addresses. Use "::" to listen on both
+    -- IPv4 and IPv6 on dual-stack hosts.
 
     MAP_PORT = 54230,
 
@@ -92,11 +94,13 @@ xi.settings.network =
     --TCP_ALLOW = "192.168.0.0/16"
     --TCP_ALLOW = "10.0.0.0/255.0.0.0"
     --TCP_ALLOW = "all"
+    --TCP_ALLOW = "::1,fd00::/8"
 
     TCP_DENY = "",
     --TCP_DENY = "10.0.0.0/8,192.168.0.0/16",
     --TCP_DENY = "127.0.0.1",
     --TCP_DENY = "10.0.0.0/255.0.0.0",
+    --TCP_DENY = "2001:db8::/32",
 
     -- ===========================
     -- Connection Limit Settings
@@ -109,7 +113,7 @@ xi.settings.network =
     -- (default is 3000 msecs, 3 seconds)
     TCP_CONNECT_INTERVAL = 3000,
 
-    -- Consecutive attempts trigger
+    -- Consecutive attempts trigger, 0 disables the lockout
     -- (default is 10 attempts)
     TCP_CONNECT_COUNT = 10,
 
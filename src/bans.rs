@@ -0,0 +1,24 @@
+use anyhow::Result;
+
+use crate::store::AccountStore;
+use crate::ACCOUNT_STATUS_CODE_BANNED;
+
+/// Checks whether an account is currently banned. Bans that have run out are
+/// lifted on the spot.
+///
+/// An account flagged as banned in `accounts.status` without a matching row in
+/// `accounts_banned` stays banned.
+pub async fn is_banned(
+    accounts: &dyn AccountStore,
+    acc_id: u32,
+    status: u32,
+) -> Result<bool> {
+    match accounts.ban_active(acc_id).await? {
+        Some(true) => Ok(true),
+        Some(false) => {
+            accounts.unban_account(acc_id).await?;
+            Ok(false)
+        }
+        None => Ok(status & ACCOUNT_STATUS_CODE_BANNED > 0),
+    }
+}
@@ -0,0 +1,377 @@
+use std::ops::RangeInclusive;
+
+use anyhow::{bail, Result};
+use thiserror::Error;
+
+use crate::login_config::LoginConfig;
+use crate::settings::Settings;
+use crate::store::CharacterStore;
+use crate::validation::{validate_char_name, CharNameError};
+
+/// Maximum number of characters per account shown in the lobby.
+pub const MAX_CHARACTERS: usize = 16;
+
+/// Jobs a new character can start as, by job id.
+const STARTING_JOBS: [&str; 6] = ["war", "mnk", "whm", "blm", "rdm", "thf"];
+
+/// Hume male to Galka, the races the lobby offers.
+const RACES: RangeInclusive<u8> = 1..=8;
+/// Eight faces, each with two hair styles.
+const FACES: RangeInclusive<u8> = 0..=15;
+/// Small, medium and large.
+const SIZES: RangeInclusive<u8> = 0..=2;
+
+/// Bit of `char_jobs.unlocked` that allows setting a support job.
+const JOBS_UNLOCKED_SUBJOB: u32 = 0x01;
+
+const INVENTORY_MIN: u8 = 30;
+const INVENTORY_MAX: u8 = 80;
+
+/// Key items are stored as tables of 512 bits, each followed by 512 bits
+/// that mark which ones have been seen.
+const KEY_ITEM_TABLES: usize = 7;
+const KEY_ITEM_TABLE_BITS: usize = 512;
+
+/// Map key items, given to new characters with `main.ALL_MAPS`.
+const MAP_KEY_ITEMS: [RangeInclusive<usize>; 2] = [385..=447, 1856..=1917];
+
+/// Conquest regions are numbered 0 to 18. Tu'Lia is 16 and the Tavnazian
+/// Archipelago is 18.
+const OUTPOST_REGIONS: u32 = (1 << 19) - 1;
+const OUTPOST_REGIONS_EXTRA: u32 = (1 << 16) | (1 << 18);
+
+/// `main` settings that shape new characters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StartConfig {
+    pub gil: u32,
+    /// Inventory and satchel size, already clamped to what the client
+    /// supports.
+    pub inventory_size: u8,
+    /// `SUBJOB_QUEST_LEVEL = 0` starts characters with support jobs.
+    pub subjob_unlocked: bool,
+    pub all_maps: bool,
+    pub outpost_warps: OutpostWarps,
+}
+
+impl StartConfig {
+    pub fn from_settings(settings: &Settings) -> Result<StartConfig> {
+        Ok(StartConfig {
+            gil: settings.try_get::<u32>("main.START_GIL")?,
+            inventory_size: inventory_size(
+                settings.try_get::<i64>("main.START_INVENTORY")?,
+            ),
+            subjob_unlocked: settings
+                .try_get::<u32>("main.SUBJOB_QUEST_LEVEL")?
+                == 0,
+            all_maps: settings.try_get::<u8>("main.ALL_MAPS")? != 0,
+            outpost_warps: OutpostWarps::from_setting(
+                settings.try_get::<u8>("main.UNLOCK_OUTPOST_WARPS")?,
+            ),
+        })
+    }
+
+    /// `char_jobs.unlocked` of a new character, as a bitmask of job ids.
+    /// Fails if a new character may not start as `main_job`.
+    pub fn unlocked_jobs(&self, main_job: u8) -> Result<u32> {
+        if starting_job(main_job).is_none() {
+            bail!("Invalid starting job {}", main_job);
+        }
+
+        let mut unlocked = 1u32 << main_job;
+        if self.subjob_unlocked {
+            unlocked |= JOBS_UNLOCKED_SUBJOB;
+        }
+        Ok(unlocked)
+    }
+}
+
+/// `main.UNLOCK_OUTPOST_WARPS`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutpostWarps {
+    None,
+    /// Every outpost but Tu'Lia and Tavnazia.
+    Regular,
+    All,
+}
+
+impl OutpostWarps {
+    fn from_setting(value: u8) -> OutpostWarps {
+        match value {
+            0 => OutpostWarps::None,
+            1 => OutpostWarps::Regular,
+            _ => OutpostWarps::All,
+        }
+    }
+
+    /// Conquest regions whose outposts can be warped to, as a bitmask.
+    pub fn regions(&self) -> u32 {
+        match self {
+            OutpostWarps::None => 0,
+            OutpostWarps::Regular => OUTPOST_REGIONS & !OUTPOST_REGIONS_EXTRA,
+            OutpostWarps::All => OUTPOST_REGIONS,
+        }
+    }
+}
+
+/// `main.START_INVENTORY` ignores values below 30, and the client cannot
+/// show more than 80 slots.
+fn inventory_size(setting: i64) -> u8 {
+    setting.clamp(INVENTORY_MIN.into(), INVENTORY_MAX.into()) as u8
+}
+
+/// Key items of a character that owns every map, and has seen them.
+pub fn map_key_items() -> Vec<u8> {
+    let table_len = KEY_ITEM_TABLE_BITS / 8 * 2;
+    let mut key_items = vec![0; KEY_ITEM_TABLES * table_len];
+
+    for key_item in MAP_KEY_ITEMS.into_iter().flatten() {
+        let table = key_item / KEY_ITEM_TABLE_BITS;
+        let bit = key_item % KEY_ITEM_TABLE_BITS;
+        let owned = table * table_len + bit / 8;
+        let seen = owned + KEY_ITEM_TABLE_BITS / 8;
+
+        key_items[owned] |= 1 << (bit % 8);
+        key_items[seen] |= 1 << (bit % 8);
+    }
+
+    key_items
+}
+
+/// Home nation of a new character.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Nation {
+    SandOria,
+    Bastok,
+    Windurst,
+}
+
+impl Nation {
+    pub fn from_id(id: u8) -> Option<Nation> {
+        match id {
+            0 => Some(Nation::SandOria),
+            1 => Some(Nation::Bastok),
+            2 => Some(Nation::Windurst),
+            _ => None,
+        }
+    }
+
+    /// Zones a character of this nation may start in.
+    fn start_zones(&self) -> &'static [u16] {
+        match self {
+            Nation::SandOria => &[230, 231, 232],
+            Nation::Bastok => &[234, 235, 236],
+            Nation::Windurst => &[238, 239, 240, 241],
+        }
+    }
+
+    /// The start zone of a new character, spread over the nation's zones
+    /// by character id.
+    pub fn start_zone(&self, char_id: u32) -> u16 {
+        let start_zones = self.start_zones();
+        start_zones[char_id as usize % start_zones.len()]
+    }
+}
+
+/// A character as listed in the lobby.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CharacterEntry {
+    pub char_id: u32,
+    pub name: String,
+    pub zone: u16,
+    pub main_job: u8,
+    pub main_job_level: u8,
+    pub race: u8,
+    pub face: u8,
+    pub size: u8,
+    /// Models of the head, body, hands, legs, feet, main and sub slots.
+    pub look: [u16; 7],
+}
+
+/// Characters to create, as picked in the lobby.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewCharacter {
+    pub name: String,
+    pub race: u8,
+    pub main_job: u8,
+    pub nation: Nation,
+    pub size: u8,
+    pub face: u8,
+}
+
+impl NewCharacter {
+    /// Column of `char_jobs` for the main job, if a new character may
+    /// start as it.
+    pub fn starting_job(&self) -> Option<&'static str> {
+        starting_job(self.main_job)
+    }
+
+    /// Whether the race, face, size and job are ones the lobby offers.
+    pub fn is_valid(&self) -> bool {
+        RACES.contains(&self.race)
+            && FACES.contains(&self.face)
+            && SIZES.contains(&self.size)
+            && self.starting_job().is_some()
+    }
+}
+
+fn starting_job(main_job: u8) -> Option<&'static str> {
+    main_job
+        .checked_sub(1)
+        .and_then(|index| STARTING_JOBS.get(index as usize))
+        .copied()
+}
+
+/// Checks a name for a new character against the rules in `login`, then
+/// against the names already in use.
+pub async fn validate_name(
+    store: &dyn CharacterStore,
+    config: &LoginConfig,
+    name: &str,
+) -> Result<Result<(), CharNameError>> {
+    if let Err(err) = validate_char_name(name, &config.banned_words) {
+        return Ok(Err(err));
+    }
+
+    if config.disable_mob_npc_char_names
+        && store.is_mob_or_npc_name(name).await?
+    {
+        return Ok(Err(CharNameError::MobOrNpc));
+    }
+
+    if store.name_taken(name).await? {
+        return Ok(Err(CharNameError::Taken));
+    }
+
+    Ok(Ok(()))
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DeletionError {
+    #[error("no such character")]
+    NotFound,
+    #[error("character is in game")]
+    Online,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_nations() {
+        assert_eq!(Nation::from_id(0), Some(Nation::SandOria));
+        assert_eq!(Nation::from_id(2), Some(Nation::Windurst));
+        assert_eq!(Nation::from_id(3), None);
+        assert!(Nation::Bastok.start_zones().contains(&234));
+        assert_eq!(Nation::Windurst.start_zone(5), 239);
+    }
+
+    #[test]
+    fn it_clamps_inventory_size() {
+        assert_eq!(inventory_size(-5), 30);
+        assert_eq!(inventory_size(10), 30);
+        assert_eq!(inventory_size(45), 45);
+        assert_eq!(inventory_size(80), 80);
+        assert_eq!(inventory_size(255), 80);
+    }
+
+    #[test]
+    fn it_unlocks_outposts() {
+        assert_eq!(OutpostWarps::from_setting(0).regions(), 0);
+
+        let regular = OutpostWarps::from_setting(1).regions();
+        assert_eq!(regular & (1 << 16), 0);
+        assert_eq!(regular & (1 << 18), 0);
+        assert_eq!(regular.count_ones(), 17);
+
+        let all = OutpostWarps::from_setting(2).regions();
+        assert_eq!(all, OUTPOST_REGIONS);
+        assert_eq!(all.count_ones(), 19);
+    }
+
+    #[test]
+    fn it_sets_map_key_items() {
+        let key_items = map_key_items();
+        assert_eq!(key_items.len(), 7 * 128);
+
+        // 385 is in the first table, 1856 in the fourth.
+        assert_eq!(key_items[385 / 8] & (1 << (385 % 8)), 1 << (385 % 8));
+        assert_ne!(key_items[64 + 385 / 8], 0);
+        assert_eq!(key_items[384 / 8] & 1, 0);
+        assert_ne!(key_items[3 * 128 + (1856 - 1536) / 8], 0);
+
+        let owned: u32 = key_items.iter().map(|b| b.count_ones()).sum();
+        assert_eq!(owned, 2 * (63 + 62));
+    }
+
+    #[test]
+    fn it_validates_new_characters() {
+        let character = crate::store::test_character();
+        assert!(character.is_valid());
+
+        for invalid in [
+            NewCharacter {
+                race: 0,
+                ..character.clone()
+            },
+            NewCharacter {
+                race: 9,
+                ..character.clone()
+            },
+            NewCharacter {
+                race: 200,
+                ..character.clone()
+            },
+            NewCharacter {
+                face: 16,
+                ..character.clone()
+            },
+            NewCharacter {
+                size: 3,
+                ..character.clone()
+            },
+            NewCharacter {
+                main_job: 0,
+                ..character.clone()
+            },
+            NewCharacter {
+                main_job: 7,
+                ..character.clone()
+            },
+        ] {
+            assert!(!invalid.is_valid(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn it_unlocks_starting_jobs_only() {
+        let lua = crate::lua::Lua::new().unwrap();
+        let settings = Settings::new(&lua).unwrap();
+        let mut config = StartConfig::from_settings(&settings).unwrap();
+
+        assert_eq!(config.unlocked_jobs(4).unwrap(), 1 << 4);
+        config.subjob_unlocked = true;
+        assert_eq!(config.unlocked_jobs(6).unwrap(), 1 << 6 | 1);
+
+        for job in [0, 7, 31, 32, 200, 255] {
+            assert!(config.unlocked_jobs(job).is_err(), "{}", job);
+        }
+    }
+
+    #[test]
+    fn it_loads_default_start_config() {
+        let lua = crate::lua::Lua::new().unwrap();
+        let settings = Settings::new(&lua).unwrap();
+
+        assert_eq!(
+            StartConfig::from_settings(&settings).unwrap(),
+            StartConfig {
+                gil: 10,
+                inventory_size: 30,
+                subjob_unlocked: false,
+                all_maps: false,
+                outpost_warps: OutpostWarps::None,
+            }
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use thiserror::Error;
+
+/// Client version in the `YYYYMMDD_R` format used by `login.CLIENT_VER`,
+/// e.g. `30221206_0`. Versions are ordered by date, then by revision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ClientVersion {
+    date: u32,
+    revision: u32,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid client version: {0:?}")]
+pub struct ParseVersionError(String);
+
+impl FromStr for ClientVersion {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseVersionError(s.to_owned());
+
+        let (date, revision) = s.split_once('_').ok_or_else(error)?;
+
+        let is_digits = |part: &str| {
+            !part.is_empty() && part.bytes().all(|c| c.is_ascii_digit())
+        };
+        if date.len() != 8 || !is_digits(date) || !is_digits(revision) {
+            return Err(error());
+        }
+
+        Ok(ClientVersion {
+            date: date.parse().map_err(|_| error())?,
+            revision: revision.parse().map_err(|_| error())?,
+        })
+    }
+}
+
+impl fmt::Display for ClientVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08}_{}", self.date, self.revision)
+    }
+}
+
+/// `login.VER_LOCK`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VersionLock {
+    /// Every version is allowed.
+    Disabled,
+    /// Only `CLIENT_VER` itself is allowed.
+    Exact,
+    /// `CLIENT_VER` and anything newer is allowed.
+    AtLeast,
+}
+
+impl VersionLock {
+    pub fn from_setting(value: u8) -> Result<VersionLock> {
+        match value {
+            0 => Ok(VersionLock::Disabled),
+            1 => Ok(VersionLock::Exact),
+            2 => Ok(VersionLock::AtLeast),
+            _ => bail!("Invalid login.VER_LOCK: {}", value),
+        }
+    }
+
+    pub fn allows(
+        &self,
+        expected: ClientVersion,
+        actual: ClientVersion,
+    ) -> bool {
+        match self {
+            VersionLock::Disabled => true,
+            VersionLock::Exact => actual == expected,
+            VersionLock::AtLeast => actual >= expected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(s: &str) -> ClientVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn it_parses_versions() {
+        assert_eq!(
+            version("30221206_0"),
+            ClientVersion {
+                date: 30221206,
+                revision: 0
+            }
+        );
+        assert_eq!(version("30221206_12").to_string(), "30221206_12");
+        assert_eq!(version("00000001_0").to_string(), "00000001_0");
+    }
+
+    #[test]
+    fn it_rejects_malformed_versions() {
+        for s in [
+            "",
+            "30221206",
+            "30221206_",
+            "_0",
+            "3022120_0",
+            "302212060_0",
+            "30221206-0",
+            "30221206_0_1",
+            "3022120a_0",
+            "30221206_-1",
+            "30221206_+1",
+            " 30221206_0",
+        ] {
+            assert!(s.parse::<ClientVersion>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn it_orders_by_date_then_revision() {
+        assert!(version("30221206_0") < version("30221206_1"));
+        assert!(version("30221206_9") < version("30221206_10"));
+        assert!(version("30221206_99") < version("30221207_0"));
+        assert!(version("30221231_5") < version("30230101_0"));
+        assert_eq!(version("30221206_00"), version("30221206_0"));
+    }
+
+    #[test]
+    fn it_applies_version_lock() {
+        let expected = version("30221206_1");
+        let older = version("30221206_0");
+        let newer = version("30221207_0");
+
+        assert!(VersionLock::Disabled.allows(expected, older));
+        assert!(VersionLock::Disabled.allows(expected, newer));
+
+        assert!(VersionLock::Exact.allows(expected, expected));
+        assert!(!VersionLock::Exact.allows(expected, older));
+        assert!(!VersionLock::Exact.allows(expected, newer));
+
+        assert!(VersionLock::AtLeast.allows(expected, expected));
+        assert!(!VersionLock::AtLeast.allows(expected, older));
+        assert!(VersionLock::AtLeast.allows(expected, newer));
+    }
+
+    #[test]
+    fn it_reads_version_lock_setting() {
+        assert_eq!(
+            VersionLock::from_setting(0).unwrap(),
+            VersionLock::Disabled
+        );
+        assert_eq!(VersionLock::from_setting(1).unwrap(), VersionLock::Exact);
+        assert_eq!(VersionLock::from_setting(2).unwrap(), VersionLock::AtLeast);
+        assert!(VersionLock::from_setting(3).is_err());
+        assert!(VersionLock::from_setting(5).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of the current time, so that lockouts can be tested without
+/// waiting for them to expire.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves when a test advances it.
+#[cfg(test)]
+pub struct ManualClock {
+    now: Mutex<Instant>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Addresses tracked before the first purge.
+const PURGE_MIN_LEN: usize = 64;
+
+struct Entry {
+    last_seen: Instant,
+    count: usize,
+    locked: bool,
+}
+
+struct Entries {
+    by_addr: HashMap<IpAddr, Entry>,
+    /// Number of addresses at which stale ones are next purged. It doubles
+    /// what is left after each purge, so a flood of new addresses does not
+    /// rescan the whole map on every connection.
+    purge_at: usize,
+}
+
+/// Per-IP connection history used for flood protection.
+///
+/// An address that connects `count` times, each within `interval` of the
+/// previous attempt, is locked out for `lockout`. A `count` of 0 disables
+/// the lockout.
+pub struct ConnectHistory {
+    count: usize,
+    interval: Duration,
+    lockout: Duration,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<Entries>,
+}
+
+impl ConnectHistory {
+    pub fn new(
+        count: usize,
+        interval: Duration,
+        lockout: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> ConnectHistory {
+        ConnectHistory {
+            count,
+            interval,
+            lockout,
+            clock,
+            entries: Mutex::new(Entries {
+                by_addr: HashMap::new(),
+                purge_at: PURGE_MIN_LEN,
+            }),
+        }
+    }
+
+    /// Records a connection attempt from `addr` and returns whether it may
+    /// proceed. Exempt addresses are tracked, but never refused.
+    pub fn record(&self, addr: IpAddr, exempt: bool) -> bool {
+        if self.count == 0 {
+            return true;
+        }
+
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.by_addr.len() >= entries.purge_at
+            && !entries.by_addr.contains_key(&addr)
+        {
+            self.purge(&mut entries, now);
+        }
+
+        let entry = entries.by_addr.entry(addr).or_insert(Entry {
+            last_seen: now,
+            count: 0,
+            locked: false,
+        });
+
+        if entry.locked && now - entry.last_seen >= self.lockout {
+            entry.locked = false;
+            entry.count = 0;
+        }
+
+        if entry.locked {
+            return exempt;
+        }
+
+        if now - entry.last_seen < self.interval {
+            entry.count += 1;
+        } else {
+            entry.count = 1;
+        }
+        entry.last_seen = now;
+
+        if entry.count >= self.count {
+            entry.locked = true;
+            return exempt;
+        }
+
+        true
+    }
+
+    /// Forgets addresses that are neither locked out nor recently active.
+    fn purge(&self, entries: &mut Entries, now: Instant) {
+        entries.by_addr.retain(|_, entry| {
+            let age = now - entry.last_seen;
+            if entry.locked {
+                age < self.lockout
+            } else {
+                age < self.interval
+            }
+        });
+        entries.purge_at = (entries.by_addr.len() * 2).max(PURGE_MIN_LEN);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> (ConnectHistory, Arc<ManualClock>) {
+        history_with_count(3)
+    }
+
+    fn history_with_count(count: usize) -> (ConnectHistory, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::default());
+
+        let history = ConnectHistory::new(
+            count,
+            Duration::from_secs(3),
+            Duration::from_secs(600),
+            clock.clone(),
+        );
+
+        (history, clock)
+    }
+
+    fn is_locked(history: &ConnectHistory, addr: IpAddr) -> bool {
+        let now = history.clock.now();
+
+        history
+            .entries
+            .lock()
+            .unwrap()
+            .by_addr
+            .get(&addr)
+            .is_some_and(|entry| {
+                entry.locked && now - entry.last_seen < history.lockout
+            })
+    }
+
+    fn addr() -> IpAddr {
+        "10.0.0.1".parse().unwrap()
+    }
+
+    #[test]
+    fn it_locks_out_after_connect_count() {
+        let (history, clock) = history();
+
+        assert!(history.record(addr(), false));
+        clock.advance(Duration::from_secs(1));
+        assert!(history.record(addr(), false));
+        clock.advance(Duration::from_secs(1));
+        assert!(!history.record(addr(), false));
+        assert!(is_locked(&history, addr()));
+
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(history.record(other, false));
+    }
+
+    #[test]
+    fn it_resets_count_after_interval() {
+        let (history, clock) = history();
+
+        for _ in 0..10 {
+            assert!(history.record(addr(), false));
+            clock.advance(Duration::from_secs(3));
+        }
+    }
+
+    #[test]
+    fn it_lifts_lockout_after_expiry() {
+        let (history, clock) = history();
+
+        for _ in 0..3 {
+            history.record(addr(), false);
+        }
+        assert!(!history.record(addr(), false));
+
+        clock.advance(Duration::from_secs(599));
+        assert!(!history.record(addr(), false));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!is_locked(&history, addr()));
+        assert!(history.record(addr(), false));
+    }
+
+    #[test]
+    fn it_does_nothing_without_connect_count() {
+        let (history, _) = history_with_count(0);
+
+        for _ in 0..10 {
+            assert!(history.record(addr(), false));
+        }
+        assert!(history.entries.lock().unwrap().by_addr.is_empty());
+    }
+
+    #[test]
+    fn it_purges_stale_addresses_as_the_history_grows() {
+        let (history, clock) = history();
+        let tracked = || history.entries.lock().unwrap().by_addr.len();
+
+        for i in 0..PURGE_MIN_LEN as u32 {
+            assert!(history.record(IpAddr::from(i.to_be_bytes()), false));
+        }
+        assert_eq!(tracked(), PURGE_MIN_LEN);
+
+        // Every address so far is stale, and goes with the next new one.
+        clock.advance(Duration::from_secs(3));
+        assert!(history.record(addr(), false));
+        assert_eq!(tracked(), 1);
+
+        // Below the threshold, new addresses are only added.
+        assert!(history.record("10.0.0.2".parse().unwrap(), false));
+        assert_eq!(tracked(), 2);
+    }
+
+    #[test]
+    fn it_never_refuses_exempt_addresses() {
+        let (history, _) = history();
+
+        for _ in 0..10 {
+            assert!(history.record(addr(), true));
+        }
+        assert!(is_locked(&history, addr()));
+    }
+}
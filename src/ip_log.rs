@@ -0,0 +1,49 @@
+use std::net::IpAddr;
+
+use anyhow::Result;
+use mysql_async::{prelude::*, Pool};
+
+/// Records a login attempt for `name` from `addr`. The account id is left
+/// empty when no account has that name.
+pub async fn record(
+    conn: &Pool,
+    name: &str,
+    addr: IpAddr,
+    success: bool,
+) -> Result<()> {
+    r#"INSERT INTO login_ip_log(login_time, accid, login, client_ip, success)
+        VALUES(NOW(), (SELECT accounts.id FROM accounts
+            WHERE accounts.login = :name), :name, :client_ip, :success)"#
+        .with(params! {
+            name,
+            "client_ip" => addr.to_canonical().to_string(),
+            success,
+        })
+        .ignore(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Accounts that have logged in successfully from `addr`, with the time of
+/// their latest login.
+pub async fn accounts_from(
+    conn: &Pool,
+    addr: IpAddr,
+) -> Result<Vec<(u32, String, String)>> {
+    let accounts = r#"SELECT login_ip_log.accid, accounts.login,
+        CAST(MAX(login_ip_log.login_time) AS CHAR)
+        FROM login_ip_log JOIN accounts
+        ON login_ip_log.accid = accounts.id
+        WHERE login_ip_log.client_ip = :client_ip
+        AND login_ip_log.success = 1
+        GROUP BY login_ip_log.accid, accounts.login
+        ORDER BY login_ip_log.accid"#
+        .with(params! {
+            "client_ip" => addr.to_canonical().to_string(),
+        })
+        .fetch(conn)
+        .await?;
+
+    Ok(accounts)
+}
@@ -0,0 +1,90 @@
+use anyhow::Result;
+use futures_util::StreamExt;
+use tokio::net::tcp::OwnedReadHalf;
+use tokio_util::codec::FramedRead;
+
+use crate::packets::lobby::{LobbyCodec, LobbyCommand, LobbyPacket};
+use crate::socket::Socket;
+
+const LOBBY_ERROR_LEN: usize = 0x24;
+const LOBBY_OK_LEN: usize = 0x20;
+
+/// No optional features, such as the extra wardrobes, are offered.
+pub const FEATURES: u32 = 0;
+
+/// Errors reported to the player through the lobby, by their client message
+/// number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LobbyError {
+    /// Unable to connect to the world server.
+    WorldConnect = 305,
+    /// The character name is not available.
+    NameUnavailable = 313,
+    /// The request cannot be served right now, e.g. during maintenance.
+    Unavailable = 314,
+    /// The client version does not match the server.
+    VersionMismatch = 331,
+}
+
+pub fn error_packet(error: LobbyError) -> Vec<u8> {
+    let mut packet = LobbyPacket::new(LobbyCommand::Error, LOBBY_ERROR_LEN);
+    packet[32..34].copy_from_slice(&(error as u16).to_le_bytes());
+    packet.seal()
+}
+
+/// Acknowledges a request that has no other reply.
+pub fn ok_packet() -> Vec<u8> {
+    LobbyPacket::new(LobbyCommand::Ok, LOBBY_OK_LEN).seal()
+}
+
+/// Splits the packets a client sends on a lobby connection.
+pub fn framed(reader: OwnedReadHalf) -> FramedRead<OwnedReadHalf, LobbyCodec> {
+    FramedRead::new(reader, LobbyCodec)
+}
+
+/// Reads the next packet, or `None` once the client disconnects.
+pub async fn read_packet(
+    socket: &Socket,
+    framed: &mut FramedRead<OwnedReadHalf, LobbyCodec>,
+) -> Result<Option<LobbyPacket>> {
+    // The client idles on lobby connections while the player looks around,
+    // so only the rest of a packet is timed.
+    if framed.read_buffer().is_empty() {
+        framed.get_ref().readable().await?;
+    }
+
+    match socket.stall_timeout(framed.next()).await? {
+        Some(packet) => Ok(Some(packet?)),
+        None => Ok(None),
+    }
+}
+
+/// Copies `s` into a NUL padded field, truncating it if needed.
+pub fn write_str(field: &mut [u8], s: &str) {
+    let len = s.len().min(field.len());
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+    field[len..].fill(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_errors() {
+        let packet = error_packet(LobbyError::WorldConnect);
+        assert_eq!(packet.len(), 0x24);
+        assert_eq!(packet[8], LobbyCommand::Error as u8);
+        assert_eq!(&packet[32..34], &305u16.to_le_bytes());
+    }
+
+    #[test]
+    fn it_pads_strings() {
+        let mut field = [0xff; 6];
+        write_str(&mut field, "abc");
+        assert_eq!(&field, b"abc\0\0\0");
+
+        write_str(&mut field, "abcdefgh");
+        assert_eq!(&field, b"abcdef");
+    }
+}
@@ -0,0 +1,239 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use spdlog::prelude::*;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::characters;
+use crate::lobby;
+use crate::login_sessions::{LobbyConnection, LobbySocket};
+use crate::packets::lobby::{LobbyCommand, LobbyPacket};
+use crate::{socket, LoginContext};
+
+const CONFIG_REPLY_LEN: usize = 0x38;
+const ACCOUNT_REPLY_LEN: usize = 0x38;
+
+/// What the client is set up with before it shows the lobby.
+fn config_reply(expansions: u32, server_name: &str) -> Vec<u8> {
+    let mut packet =
+        LobbyPacket::new(LobbyCommand::ConfigReply, CONFIG_REPLY_LEN);
+    packet[32..36].copy_from_slice(&expansions.to_le_bytes());
+    packet[36..40].copy_from_slice(&lobby::FEATURES.to_le_bytes());
+    lobby::write_str(&mut packet[40..56], server_name);
+    packet.seal()
+}
+
+/// The account the client logged in as, and how many of its character
+/// slots are in use.
+fn account_reply(acc_id: u32, login: &str, characters: usize) -> Vec<u8> {
+    let mut packet =
+        LobbyPacket::new(LobbyCommand::AccountReply, ACCOUNT_REPLY_LEN);
+    packet[32..36].copy_from_slice(&acc_id.to_le_bytes());
+    packet[36] = characters.min(characters::MAX_CHARACTERS) as u8;
+    packet[37] = characters::MAX_CHARACTERS as u8;
+    lobby::write_str(&mut packet[40..56], login);
+    packet.seal()
+}
+
+pub async fn run(listener: TcpListener, ctx: Arc<LoginContext>) -> Result<()> {
+    socket::serve(listener, ctx.socket.clone(), move |stream, addr| {
+        let ctx = ctx.clone();
+        async move { handle(stream, addr, &ctx).await }
+    })
+    .await
+}
+
+/// The client asks for the configuration of the server and the details of
+/// its account on this connection while it sets up the lobby.
+async fn handle(
+    stream: TcpStream,
+    client: SocketAddr,
+    ctx: &LoginContext,
+) -> Result<()> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = lobby::framed(reader);
+    let writer = socket::spawn_writer(writer);
+
+    // Like the view connection, this one does not name its account.
+    let acc_id = ctx
+        .sessions
+        .attach_by_client(client.ip(), LobbyConnection::Conf, writer.clone())
+        .ok_or_else(|| anyhow!("no login session from {}", client))?;
+
+    let result = async {
+        while let Some(packet) =
+            lobby::read_packet(&ctx.socket, &mut reader).await?
+        {
+            process(ctx, client, acc_id, &writer, &packet).await?;
+        }
+        Ok(())
+    }
+    .await;
+
+    ctx.sessions.detach(acc_id, LobbyConnection::Conf);
+
+    result
+}
+
+async fn process(
+    ctx: &LoginContext,
+    client: SocketAddr,
+    acc_id: u32,
+    writer: &LobbySocket,
+    packet: &LobbyPacket,
+) -> Result<()> {
+    match packet.command() {
+        Some(LobbyCommand::ConfigRequest) => {
+            writer.send(config_reply(
+                ctx.config.expansions,
+                &ctx.config.server_name,
+            ))?;
+        }
+        Some(LobbyCommand::AccountRequest) => {
+            let login = ctx
+                .sessions
+                .update(acc_id, client.ip(), |session| {
+                    session.login().to_owned()
+                })
+                .ok_or_else(|| anyhow!("account {} logged out", acc_id))?;
+            let characters =
+                ctx.characters.list_characters(acc_id).await?.len();
+
+            writer.send(account_reply(acc_id, &login, characters))?;
+        }
+        _ => {
+            warn!(
+                logger: ctx.logger,
+                "Unexpected lobby conf packet {:#04x} from {}",
+                packet.command_id(),
+                client
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::login_sessions::LoginSessionData;
+    use crate::store::{
+        test_character, test_client, CharacterStore, MemoryStore,
+        TEST_ACC_ID as ACC_ID,
+    };
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+
+    fn conf(
+        store: Arc<MemoryStore>,
+    ) -> (LoginContext, LobbySocket, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let ctx = LoginContext::for_tests(store);
+        ctx.sessions.insert(LoginSessionData::new(
+            ACC_ID,
+            "shantotto",
+            test_client(),
+        ));
+
+        let (writer, replies) = mpsc::unbounded_channel();
+        (ctx, writer, replies)
+    }
+
+    async fn send(
+        ctx: &LoginContext,
+        writer: &LobbySocket,
+        command: LobbyCommand,
+    ) {
+        let packet = LobbyPacket::new(command, 0x20);
+        process(ctx, test_client(), ACC_ID, writer, &packet)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_replies_with_the_server_config() {
+        let (ctx, writer, mut replies) = conf(Default::default());
+
+        send(&ctx, &writer, LobbyCommand::ConfigRequest).await;
+        let reply = replies.recv().await.unwrap();
+        assert_eq!(reply.len(), CONFIG_REPLY_LEN);
+        assert_eq!(reply[8], LobbyCommand::ConfigReply as u8);
+        assert_eq!(&reply[32..36], &ctx.config.expansions.to_le_bytes());
+        assert_eq!(&reply[40..49], b"Nameless\0");
+    }
+
+    #[tokio::test]
+    async fn it_replies_with_the_account() {
+        let store = Arc::new(MemoryStore::default());
+        let (ctx, writer, mut replies) = conf(store.clone());
+        store
+            .create_character(
+                &ctx.config.new_character,
+                ACC_ID,
+                &test_character(),
+            )
+            .await
+            .unwrap();
+
+        send(&ctx, &writer, LobbyCommand::AccountRequest).await;
+        let reply = replies.recv().await.unwrap();
+        assert_eq!(reply.len(), ACCOUNT_REPLY_LEN);
+        assert_eq!(reply[8], LobbyCommand::AccountReply as u8);
+        assert_eq!(&reply[32..36], &ACC_ID.to_le_bytes());
+        assert_eq!(reply[36], 1);
+        assert_eq!(reply[37], characters::MAX_CHARACTERS as u8);
+        assert_eq!(&reply[40..50], b"shantotto\0");
+    }
+
+    #[tokio::test]
+    async fn it_serves_the_conf_connection() {
+        let ctx = Arc::new(LoginContext::for_tests(Default::default()));
+        let local = "127.0.0.1:50000".parse().unwrap();
+        ctx.sessions
+            .insert(LoginSessionData::new(ACC_ID, "shantotto", local));
+        let attached = || {
+            ctx.sessions
+                .update(ACC_ID, local.ip(), |session| {
+                    session.login_lobbyconf_socket.is_some()
+                })
+                .unwrap()
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run(listener, ctx.clone()));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let request = LobbyPacket::new(LobbyCommand::AccountRequest, 0x20);
+        client.write_all(&request.seal()).await.unwrap();
+
+        let mut reply = [0; ACCOUNT_REPLY_LEN];
+        timeout(Duration::from_secs(5), client.read_exact(&mut reply))
+            .await
+            .expect("no reply on the conf connection")
+            .unwrap();
+        assert_eq!(reply[8], LobbyCommand::AccountReply as u8);
+        assert_eq!(&reply[32..36], &ACC_ID.to_le_bytes());
+        assert!(attached());
+
+        drop(client);
+        timeout(Duration::from_secs(5), async {
+            while attached() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("conf connection was not detached");
+    }
+
+    #[tokio::test]
+    async fn it_ignores_other_packets() {
+        let (ctx, writer, mut replies) = conf(Default::default());
+
+        send(&ctx, &writer, LobbyCommand::CharList).await;
+        assert!(replies.try_recv().is_err());
+    }
+}
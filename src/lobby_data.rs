@@ -0,0 +1,405 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use spdlog::prelude::*;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::characters::MAX_CHARACTERS;
+use crate::lobby::{self, LobbyError};
+use crate::login_sessions::{LobbyConnection, LobbySocket};
+use crate::packets::lobby::{LobbyCommand, LobbyPacket};
+use crate::store::GameSession;
+use crate::{lobby_view, socket, LoginContext};
+
+/// Sent by the client to link the connection to its login session.
+const LOBBY_DATA_LINK: u8 = 0xA1;
+/// Sent by the client with its session key once a character is selected.
+const LOBBY_DATA_SELECT: u8 = 0xA2;
+
+const LOBBY_DATA_LINK_LEN: usize = 8;
+const LOBBY_DATA_SELECT_LEN: usize = 20;
+
+const CHAR_LIST_LEN: usize = 0x148;
+const CHAR_LIST_MAGIC: u8 = 0x03;
+
+const RESERVATION_LEN: usize = 0x48;
+
+/// Where a character should connect to enter the world.
+#[derive(Debug, PartialEq, Eq)]
+struct Reservation {
+    char_id: u32,
+    char_name: String,
+    zone_addr: Ipv4Addr,
+    zone_port: u16,
+    search_addr: Ipv4Addr,
+    search_port: u16,
+}
+
+impl Reservation {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut packet =
+            LobbyPacket::new(LobbyCommand::Reservation, RESERVATION_LEN);
+        packet[28..32].copy_from_slice(&self.char_id.to_le_bytes());
+        lobby::write_str(&mut packet[32..48], &self.char_name);
+        packet[56..60].copy_from_slice(&self.zone_addr.octets());
+        packet[60..62].copy_from_slice(&self.zone_port.to_le_bytes());
+        packet[64..68].copy_from_slice(&self.search_addr.octets());
+        packet[68..70].copy_from_slice(&self.search_port.to_le_bytes());
+        packet.seal()
+    }
+}
+
+/// Ids of the characters of an account, as the data connection expects
+/// them. Each id appears twice, in 16 byte slots after the header.
+fn char_list(char_ids: &[u32]) -> Vec<u8> {
+    let mut list = vec![0; CHAR_LIST_LEN];
+    let char_ids = &char_ids[..char_ids.len().min(MAX_CHARACTERS)];
+
+    list[0] = CHAR_LIST_MAGIC;
+    list[1] = char_ids.len() as u8;
+
+    for (i, char_id) in char_ids.iter().enumerate() {
+        let offset = 16 * (i + 1);
+        list[offset..offset + 4].copy_from_slice(&char_id.to_le_bytes());
+        list[offset + 4..offset + 8].copy_from_slice(&char_id.to_le_bytes());
+    }
+
+    list
+}
+
+pub async fn run(listener: TcpListener, ctx: Arc<LoginContext>) -> Result<()> {
+    socket::serve(listener, ctx.socket.clone(), move |stream, addr| {
+        let ctx = ctx.clone();
+        async move { handle(stream, addr, &ctx).await }
+    })
+    .await
+}
+
+async fn handle(
+    stream: TcpStream,
+    client: SocketAddr,
+    ctx: &LoginContext,
+) -> Result<()> {
+    let (mut reader, writer) = stream.into_split();
+    let writer = socket::spawn_writer(writer);
+    let mut acc_id = None;
+
+    let result = async {
+        loop {
+            // The client idles on this connection while the player looks
+            // through the lobby, so only the body of a packet is timed.
+            let code = match reader.read_u8().await {
+                Ok(code) => code,
+                Err(_) => return Ok(()),
+            };
+
+            match code {
+                LOBBY_DATA_LINK => {
+                    let mut body = [0; LOBBY_DATA_LINK_LEN];
+                    ctx.socket.read_exact(&mut reader, &mut body).await?;
+
+                    let id = u32::from_le_bytes(body[0..4].try_into()?);
+                    link(ctx, client, id, &writer).await?;
+                    acc_id = Some(id);
+                }
+                LOBBY_DATA_SELECT => {
+                    let mut key = [0; LOBBY_DATA_SELECT_LEN];
+                    ctx.socket.read_exact(&mut reader, &mut key).await?;
+
+                    let id = acc_id.ok_or_else(|| {
+                        anyhow!("character selected before linking")
+                    })?;
+                    select(ctx, client, id, key).await?;
+                }
+                _ => bail!("unknown lobby data packet {:#04x}", code),
+            }
+        }
+    }
+    .await;
+
+    // The lobby is done with the session once its data connection closes.
+    if let Some(acc_id) = acc_id {
+        ctx.sessions.remove(acc_id);
+    }
+
+    result
+}
+
+/// Attaches the connection to the session of the account that logged in
+/// from `client`, then sends its character list. The client asks again
+/// whenever the view connection needs the list, which gets the full entries.
+async fn link(
+    ctx: &LoginContext,
+    client: SocketAddr,
+    acc_id: u32,
+    writer: &LobbySocket,
+) -> Result<()> {
+    if !ctx.sessions.attach(
+        acc_id,
+        client.ip(),
+        LobbyConnection::Data,
+        writer.clone(),
+    ) {
+        bail!("no login session for account {} from {}", acc_id, client);
+    }
+
+    let characters = ctx.characters.list_characters(acc_id).await?;
+    let char_ids: Vec<u32> = characters
+        .iter()
+        .map(|character| character.char_id)
+        .collect();
+    writer.send(char_list(&char_ids))?;
+
+    let view = ctx
+        .sessions
+        .update(acc_id, client.ip(), |session| {
+            session.login_lobbyview_socket.clone()
+        })
+        .flatten();
+
+    if let Some(view) = view {
+        view.send(lobby_view::char_list(&ctx.config.server_name, &characters))?;
+    }
+
+    Ok(())
+}
+
+/// Reserves a place for the selected character on its zone server and
+/// tells the view connection where to go.
+async fn select(
+    ctx: &LoginContext,
+    client: SocketAddr,
+    acc_id: u32,
+    mut key: [u8; LOBBY_DATA_SELECT_LEN],
+) -> Result<()> {
+    let Some((char_id, view, version_mismatch, first_login)) = ctx
+        .sessions
+        .update(acc_id, client.ip(), |session| {
+            let first_login =
+                std::mem::take(&mut session.just_created_new_char);
+
+            session
+                .selected_char
+                .zip(session.login_lobbyview_socket.clone())
+                .map(|(char_id, view)| {
+                    (char_id, view, session.version_mismatch, first_login)
+                })
+        })
+        .flatten()
+    else {
+        bail!("account {} selected a character without a view", acc_id);
+    };
+
+    let Some(entry) = ctx.characters.world_entry(acc_id, char_id).await? else {
+        error!(
+            logger: ctx.logger,
+            "No zone server found for character {}", char_id
+        );
+        view.send(lobby::error_packet(LobbyError::WorldConnect))?;
+        return Ok(());
+    };
+
+    if ctx.maint_mode() && entry.gm_level == 0 {
+        view.send(lobby::error_packet(LobbyError::Unavailable))?;
+        return Ok(());
+    }
+
+    // The map server expects the key the client will present to it.
+    key[16] = key[16].wrapping_sub(2);
+
+    let client_addr = match client.ip().to_canonical() {
+        IpAddr::V4(addr) => u32::from(addr),
+        IpAddr::V6(_) => 0,
+    };
+
+    ctx.accounts
+        .start_game_session(&GameSession {
+            acc_id,
+            char_id,
+            session_key: key.to_vec(),
+            server: entry.zone_server,
+            client_addr,
+            version_mismatch,
+        })
+        .await?;
+
+    let zone_addr = *entry.zone_server.ip();
+    let zone_port = entry.zone_server.port();
+    let reservation = Reservation {
+        char_id,
+        char_name: entry.char_name,
+        zone_addr,
+        zone_port,
+        search_addr: zone_addr,
+        search_port: ctx.search_port,
+    };
+
+    if first_login {
+        info!(
+            logger: ctx.logger,
+            "Character {} is entering the world for the first time", char_id
+        );
+    }
+
+    info!(
+        logger: ctx.logger,
+        "Account {} entering the world with character {} on {}:{}",
+        acc_id,
+        char_id,
+        zone_addr,
+        zone_port
+    );
+
+    view.send(reservation.to_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::login_sessions::LoginSessionData;
+    use crate::store::{
+        test_character, test_client, AccountStore, CharacterStore, MemoryStore,
+        TEST_ACC_ID as ACC_ID,
+    };
+    use tokio::sync::mpsc;
+
+    /// Logs an account in with a character and a view connection, and
+    /// returns the id of the character along with what the view receives.
+    async fn lobby(
+        store: &MemoryStore,
+        ctx: &LoginContext,
+    ) -> (u32, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let client = test_client();
+        ctx.sessions
+            .insert(LoginSessionData::new(ACC_ID, "shantotto", client));
+
+        let (view, replies) = mpsc::unbounded_channel();
+        ctx.sessions
+            .attach(ACC_ID, client.ip(), LobbyConnection::View, view);
+
+        let char_id = store
+            .create_character(
+                &ctx.config.new_character,
+                ACC_ID,
+                &test_character(),
+            )
+            .await
+            .unwrap();
+
+        (char_id, replies)
+    }
+
+    #[test]
+    fn it_encodes_char_list() {
+        let list = char_list(&[21828, 21829]);
+        assert_eq!(list.len(), CHAR_LIST_LEN);
+        assert_eq!(list[0], CHAR_LIST_MAGIC);
+        assert_eq!(list[1], 2);
+        assert_eq!(&list[16..20], &21828u32.to_le_bytes());
+        assert_eq!(&list[20..24], &21828u32.to_le_bytes());
+        assert_eq!(&list[32..36], &21829u32.to_le_bytes());
+        assert_eq!(&list[48..56], &[0; 8]);
+    }
+
+    #[test]
+    fn it_caps_char_list() {
+        let char_ids: Vec<u32> = (1..=20).collect();
+        let list = char_list(&char_ids);
+        assert_eq!(list[1], MAX_CHARACTERS as u8);
+    }
+
+    #[test]
+    fn it_encodes_reservation() {
+        let packet = Reservation {
+            char_id: 21828,
+            char_name: "Shantotto".to_owned(),
+            zone_addr: Ipv4Addr::new(127, 0, 0, 1),
+            zone_port: 54230,
+            search_addr: Ipv4Addr::new(127, 0, 0, 1),
+            search_port: 54002,
+        }
+        .to_bytes();
+
+        assert_eq!(packet.len(), RESERVATION_LEN);
+        assert_eq!(packet[8], LobbyCommand::Reservation as u8);
+        assert_eq!(&packet[28..32], &21828u32.to_le_bytes());
+        assert_eq!(&packet[32..42], b"Shantotto\0");
+        assert_eq!(&packet[56..60], &[127, 0, 0, 1]);
+        assert_eq!(&packet[60..62], &54230u16.to_le_bytes());
+        assert_eq!(&packet[68..70], &54002u16.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn it_links_and_lists_characters() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = LoginContext::for_tests(store.clone());
+        let (char_id, mut view) = lobby(&store, &ctx).await;
+
+        let (writer, mut data) = mpsc::unbounded_channel();
+        let client = test_client();
+        link(&ctx, client, ACC_ID, &writer).await.unwrap();
+
+        assert_eq!(data.recv().await.unwrap(), char_list(&[char_id]));
+        let characters = store.list_characters(ACC_ID).await.unwrap();
+        assert_eq!(
+            view.recv().await.unwrap(),
+            lobby_view::char_list("Nameless", &characters)
+        );
+
+        // Only an account that logged in from the address can link.
+        assert!(link(&ctx, client, ACC_ID + 1, &writer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_sends_characters_into_the_world() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = LoginContext::for_tests(store.clone());
+        let (char_id, mut view) = lobby(&store, &ctx).await;
+        let client = test_client();
+        let select = || select(&ctx, client, ACC_ID, [0; 20]);
+
+        let zone = test_character().nation.start_zone(char_id);
+        let zone_server = "127.0.0.1:54230".parse().unwrap();
+
+        ctx.sessions.update(ACC_ID, client.ip(), |session| {
+            session.selected_char = Some(char_id);
+        });
+
+        select().await.unwrap();
+        assert_eq!(
+            view.recv().await.unwrap(),
+            lobby::error_packet(LobbyError::WorldConnect)
+        );
+
+        store.set_zone_server(zone, zone_server);
+        ctx.set_maint_mode(true);
+        select().await.unwrap();
+        assert_eq!(
+            view.recv().await.unwrap(),
+            lobby::error_packet(LobbyError::Unavailable)
+        );
+        assert!(store.game_session(ACC_ID).await.unwrap().is_none());
+
+        store.set_gm_level(char_id, 1);
+        select().await.unwrap();
+        let reservation = Reservation {
+            char_id,
+            char_name: test_character().name,
+            zone_addr: Ipv4Addr::new(127, 0, 0, 1),
+            zone_port: 54230,
+            search_addr: Ipv4Addr::new(127, 0, 0, 1),
+            search_port: 54002,
+        };
+        assert_eq!(view.recv().await.unwrap(), reservation.to_bytes());
+
+        let session = store.game_session(ACC_ID).await.unwrap().unwrap();
+        assert_eq!(session.char_id, char_id);
+        assert_eq!(session.server, zone_server);
+        assert_eq!(session.client_addr, u32::from(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(session.session_key[16], 0xFE);
+    }
+}
@@ -0,0 +1,676 @@
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use spdlog::prelude::*;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::characters::{self, CharacterEntry, Nation, NewCharacter};
+use crate::client_version::ClientVersion;
+use crate::lobby::{self, LobbyError};
+use crate::login_sessions::{LobbyConnection, LobbySocket};
+use crate::packets::lobby::{LobbyCommand, LobbyPacket};
+use crate::packets::read_field;
+use crate::{socket, LoginContext};
+
+/// Prompts sent on the data connection, which the client answers there.
+const LOBBY_DATA_SEND_CHAR_LIST: u8 = 0x01;
+const LOBBY_DATA_SEND_KEY: u8 = 0x02;
+
+const CLIENT_VERSION: Range<usize> = 0x74..0x84;
+const SELECT_CHAR_ID: Range<usize> = 28..32;
+const RESERVE_NAME: Range<usize> = 32..48;
+const DELETE_CHAR_ID: Range<usize> = 32..36;
+const CREATE_RACE: usize = 48;
+const CREATE_MAIN_JOB: usize = 50;
+const CREATE_NATION: usize = 54;
+const CREATE_SIZE: usize = 57;
+const CREATE_FACE: usize = 60;
+
+const VERSION_REPLY_LEN: usize = 0x28;
+
+const WORLD_LIST_LEN: usize = 0x40;
+
+const CHAR_LIST_LEN: usize = 0x8E0;
+const CHAR_ENTRY_LEN: usize = 140;
+
+/// Lists the characters of an account for the character select screen.
+pub fn char_list(server_name: &str, characters: &[CharacterEntry]) -> Vec<u8> {
+    let mut packet =
+        LobbyPacket::new(LobbyCommand::CharListReply, CHAR_LIST_LEN);
+    let characters =
+        &characters[..characters.len().min(characters::MAX_CHARACTERS)];
+
+    packet[28] = characters.len() as u8;
+
+    for (i, character) in characters.iter().enumerate() {
+        let entry = &mut packet[32 + i * CHAR_ENTRY_LEN..][..CHAR_ENTRY_LEN];
+
+        entry[0..4].copy_from_slice(&character.char_id.to_le_bytes());
+        entry[4..8].copy_from_slice(&character.char_id.to_le_bytes());
+        lobby::write_str(&mut entry[12..28], &character.name);
+        lobby::write_str(&mut entry[28..44], server_name);
+        entry[44] = character.race;
+        entry[46] = character.main_job;
+        entry[56] = character.face;
+        entry[57] = character.size;
+        for (slot, model) in character.look.iter().enumerate() {
+            let offset = 58 + slot * 2;
+            entry[offset..offset + 2].copy_from_slice(&model.to_le_bytes());
+        }
+        entry[72] = character.zone as u8;
+        entry[73] = character.main_job_level;
+        entry[78..80].copy_from_slice(&character.zone.to_le_bytes());
+    }
+
+    packet.seal()
+}
+
+fn version_reply(expansions: u32) -> Vec<u8> {
+    let mut packet =
+        LobbyPacket::new(LobbyCommand::VersionReply, VERSION_REPLY_LEN);
+    packet[32..36].copy_from_slice(&expansions.to_le_bytes());
+    packet[36..40].copy_from_slice(&lobby::FEATURES.to_le_bytes());
+    packet.seal()
+}
+
+fn world_list(server_name: &str) -> Vec<u8> {
+    let mut packet =
+        LobbyPacket::new(LobbyCommand::WorldListReply, WORLD_LIST_LEN);
+    packet[28] = 1;
+    lobby::write_str(&mut packet[36..52], server_name);
+    packet.seal()
+}
+
+fn field(packet: &[u8], range: Range<usize>) -> Result<&[u8]> {
+    packet
+        .get(range)
+        .ok_or_else(|| anyhow!("lobby view packet too short"))
+}
+
+fn byte(packet: &[u8], offset: usize) -> Result<u8> {
+    Ok(field(packet, offset..offset + 1)?[0])
+}
+
+fn read_u32(packet: &[u8], range: Range<usize>) -> Result<u32> {
+    Ok(u32::from_le_bytes(field(packet, range)?.try_into()?))
+}
+
+pub async fn run(listener: TcpListener, ctx: Arc<LoginContext>) -> Result<()> {
+    socket::serve(listener, ctx.socket.clone(), move |stream, addr| {
+        let ctx = ctx.clone();
+        async move { handle(stream, addr, &ctx).await }
+    })
+    .await
+}
+
+async fn handle(
+    stream: TcpStream,
+    client: SocketAddr,
+    ctx: &LoginContext,
+) -> Result<()> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = lobby::framed(reader);
+    let writer = socket::spawn_writer(writer);
+
+    // Nothing in the view protocol names the account, so the connection
+    // goes with the latest login from the same address.
+    let acc_id = ctx
+        .sessions
+        .attach_by_client(client.ip(), LobbyConnection::View, writer.clone())
+        .ok_or_else(|| anyhow!("no login session from {}", client))?;
+
+    while let Some(packet) =
+        lobby::read_packet(&ctx.socket, &mut reader).await?
+    {
+        process(ctx, client, acc_id, &writer, &packet).await?;
+    }
+
+    Ok(())
+}
+
+async fn process(
+    ctx: &LoginContext,
+    client: SocketAddr,
+    acc_id: u32,
+    writer: &LobbySocket,
+    packet: &LobbyPacket,
+) -> Result<()> {
+    let update = |f: &mut dyn FnMut(&mut _)| {
+        ctx.sessions
+            .update(acc_id, client.ip(), |session| f(session))
+    };
+
+    let Some(command) = packet.command() else {
+        warn!(
+            logger: ctx.logger,
+            "Unknown lobby view packet {:#04x} from {}",
+            packet.command_id(),
+            client
+        );
+        return Ok(());
+    };
+
+    let mut version_accepted = false;
+    update(&mut |session| version_accepted = session.version_accepted);
+
+    if command != LobbyCommand::Version && !version_accepted {
+        warn!(
+            logger: ctx.logger,
+            "Lobby view packet {:?} from {} before an accepted version",
+            command,
+            client
+        );
+        writer.send(lobby::error_packet(LobbyError::VersionMismatch))?;
+        return Ok(());
+    }
+
+    match command {
+        LobbyCommand::Version => {
+            let version = read_field(field(packet, CLIENT_VERSION)?)
+                .and_then(|version| version.parse::<ClientVersion>().ok());
+
+            if !ctx.config.allows_client(version) {
+                info!(
+                    logger: ctx.logger,
+                    "Refused client of account {}, version {:?} is not allowed",
+                    acc_id,
+                    version
+                );
+                writer
+                    .send(lobby::error_packet(LobbyError::VersionMismatch))?;
+                return Ok(());
+            }
+
+            update(&mut |session| {
+                session.version_accepted = true;
+                session.version_mismatch =
+                    version != Some(ctx.config.client_ver);
+            });
+
+            writer.send(version_reply(ctx.config.expansions))?;
+        }
+        LobbyCommand::CharList => {
+            send_data(ctx, client, acc_id, LOBBY_DATA_SEND_CHAR_LIST)?;
+        }
+        LobbyCommand::WorldList => {
+            writer.send(world_list(&ctx.config.server_name))?;
+        }
+        LobbyCommand::Select => {
+            let char_id = read_u32(packet, SELECT_CHAR_ID)?;
+            update(&mut |session| session.selected_char = Some(char_id));
+            send_data(ctx, client, acc_id, LOBBY_DATA_SEND_KEY)?;
+        }
+        LobbyCommand::ReserveName => {
+            let name = read_field(field(packet, RESERVE_NAME)?)
+                .unwrap_or_default()
+                .to_owned();
+
+            let reply = reserve_name(ctx, &name).await?;
+            if reply.is_ok() {
+                update(&mut |session| session.char_name = Some(name.clone()));
+            }
+
+            writer.send(
+                reply.map_or_else(lobby::error_packet, |_| lobby::ok_packet()),
+            )?;
+        }
+        LobbyCommand::Create => {
+            let mut name = None;
+            update(&mut |session| name = session.char_name.clone());
+
+            // The reservation is only used up by a creation that went
+            // through.
+            let reply = create(ctx, acc_id, name, packet).await?;
+            if reply.is_ok() {
+                update(&mut |session| {
+                    session.char_name = None;
+                    session.just_created_new_char = true;
+                });
+            }
+
+            writer.send(
+                reply.map_or_else(lobby::error_packet, |_| lobby::ok_packet()),
+            )?;
+        }
+        LobbyCommand::Delete => {
+            let char_id = read_u32(packet, DELETE_CHAR_ID)?;
+            let reply = delete(ctx, acc_id, char_id).await?;
+
+            writer.send(
+                reply.map_or_else(lobby::error_packet, |_| lobby::ok_packet()),
+            )?;
+        }
+        command => {
+            warn!(
+                logger: ctx.logger,
+                "Unexpected lobby view packet {:?} from {}", command, client
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Asks the client for something on its data connection.
+fn send_data(
+    ctx: &LoginContext,
+    client: SocketAddr,
+    acc_id: u32,
+    prompt: u8,
+) -> Result<()> {
+    let data = ctx
+        .sessions
+        .update(acc_id, client.ip(), |session| {
+            session.login_lobbydata_socket.clone()
+        })
+        .flatten()
+        .ok_or_else(|| anyhow!("account {} has no data connection", acc_id))?;
+
+    data.send(vec![prompt])?;
+
+    Ok(())
+}
+
+async fn reserve_name(
+    ctx: &LoginContext,
+    name: &str,
+) -> Result<Result<(), LobbyError>> {
+    if ctx.maint_mode() {
+        return Ok(Err(LobbyError::Unavailable));
+    }
+
+    check_name(ctx, name).await
+}
+
+/// Validates the name of a new character, logging why it was refused.
+async fn check_name(
+    ctx: &LoginContext,
+    name: &str,
+) -> Result<Result<(), LobbyError>> {
+    match characters::validate_name(&*ctx.characters, &ctx.config, name).await?
+    {
+        Ok(()) => Ok(Ok(())),
+        Err(err) => {
+            info!(
+                logger: ctx.logger,
+                "Refused character name {}: {}", name, err
+            );
+            Ok(Err(LobbyError::NameUnavailable))
+        }
+    }
+}
+
+async fn create(
+    ctx: &LoginContext,
+    acc_id: u32,
+    name: Option<String>,
+    packet: &[u8],
+) -> Result<Result<(), LobbyError>> {
+    if ctx.maint_mode() {
+        return Ok(Err(LobbyError::Unavailable));
+    }
+
+    let Some(name) = name else {
+        return Ok(Err(LobbyError::NameUnavailable));
+    };
+
+    let Some(character) = new_character(packet, name)? else {
+        info!(
+            logger: ctx.logger,
+            "Refused character of account {}, not one the lobby offers",
+            acc_id
+        );
+        return Ok(Err(LobbyError::Unavailable));
+    };
+
+    // Another account may have taken the name since it was reserved.
+    if let Err(err) = check_name(ctx, &character.name).await? {
+        return Ok(Err(err));
+    }
+
+    if ctx.characters.list_characters(acc_id).await?.len()
+        >= characters::MAX_CHARACTERS
+    {
+        return Ok(Err(LobbyError::Unavailable));
+    }
+
+    let char_id = ctx
+        .characters
+        .create_character(&ctx.config.new_character, acc_id, &character)
+        .await?;
+
+    info!(
+        logger: ctx.logger,
+        "Account {} created character {} ({})", acc_id, character.name, char_id
+    );
+
+    Ok(Ok(()))
+}
+
+/// Reads the character a create packet asks for, or `None` if it has a
+/// nation, race, look or job a new character cannot have.
+fn new_character(packet: &[u8], name: String) -> Result<Option<NewCharacter>> {
+    let Some(nation) = Nation::from_id(byte(packet, CREATE_NATION)?) else {
+        return Ok(None);
+    };
+
+    let character = NewCharacter {
+        name,
+        race: byte(packet, CREATE_RACE)?,
+        main_job: byte(packet, CREATE_MAIN_JOB)?,
+        nation,
+        size: byte(packet, CREATE_SIZE)?,
+        face: byte(packet, CREATE_FACE)?,
+    };
+
+    Ok(character.is_valid().then_some(character))
+}
+
+async fn delete(
+    ctx: &LoginContext,
+    acc_id: u32,
+    char_id: u32,
+) -> Result<Result<(), LobbyError>> {
+    if !ctx.config.character_deletion || ctx.maint_mode() {
+        return Ok(Err(LobbyError::Unavailable));
+    }
+
+    if let Err(err) = ctx.characters.delete_character(acc_id, char_id).await? {
+        info!(
+            logger: ctx.logger,
+            "Refused deletion of character {}: {}", char_id, err
+        );
+        return Ok(Err(LobbyError::Unavailable));
+    }
+
+    info!(
+        logger: ctx.logger,
+        "Account {} deleted character {}", acc_id, char_id
+    );
+
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_version::VersionLock;
+    use crate::login_sessions::LoginSessionData;
+    use crate::store::{
+        test_character, test_client, CharacterStore, MemoryStore,
+        TEST_ACC_ID as ACC_ID,
+    };
+    use tokio::sync::mpsc;
+
+    /// Starts the lobby of an account that has just logged in and sent an
+    /// accepted version, with the channel its view connection writes to.
+    fn lobby(
+        store: Arc<MemoryStore>,
+    ) -> (LoginContext, LobbySocket, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let ctx = LoginContext::for_tests(store);
+        let mut session =
+            LoginSessionData::new(ACC_ID, "shantotto", test_client());
+        session.version_accepted = true;
+        ctx.sessions.insert(session);
+
+        let (writer, replies) = mpsc::unbounded_channel();
+        (ctx, writer, replies)
+    }
+
+    async fn send(
+        ctx: &LoginContext,
+        writer: &LobbySocket,
+        packet: LobbyPacket,
+    ) {
+        process(ctx, test_client(), ACC_ID, writer, &packet)
+            .await
+            .unwrap();
+    }
+
+    fn session<R>(
+        ctx: &LoginContext,
+        f: impl FnOnce(&mut LoginSessionData) -> R,
+    ) -> R {
+        ctx.sessions.update(ACC_ID, test_client().ip(), f).unwrap()
+    }
+
+    fn version(version: &str) -> LobbyPacket {
+        let mut packet = LobbyPacket::new(LobbyCommand::Version, 0x98);
+        lobby::write_str(&mut packet[CLIENT_VERSION], version);
+        packet
+    }
+
+    fn reserve(name: &str) -> LobbyPacket {
+        let mut packet = LobbyPacket::new(LobbyCommand::ReserveName, 0x98);
+        lobby::write_str(&mut packet[RESERVE_NAME], name);
+        packet
+    }
+
+    fn create() -> LobbyPacket {
+        let character = test_character();
+        let mut packet = LobbyPacket::new(LobbyCommand::Create, 0x64);
+        packet[CREATE_RACE] = character.race;
+        packet[CREATE_MAIN_JOB] = character.main_job;
+        packet[CREATE_NATION] = character.nation as u8;
+        packet[CREATE_SIZE] = character.size;
+        packet[CREATE_FACE] = character.face;
+        packet
+    }
+
+    /// The lobby error in a reply, or `None` if it is an OK.
+    fn reply_error(reply: Vec<u8>) -> Option<u16> {
+        match LobbyCommand::from_id(reply[8]) {
+            Some(LobbyCommand::Ok) => None,
+            Some(LobbyCommand::Error) => {
+                Some(u16::from_le_bytes([reply[32], reply[33]]))
+            }
+            command => panic!("unexpected reply {:?}", command),
+        }
+    }
+
+    #[test]
+    fn it_encodes_char_list() {
+        let character = CharacterEntry {
+            char_id: 21828,
+            name: "Shantotto".to_owned(),
+            zone: 238,
+            main_job: 4,
+            main_job_level: 75,
+            race: 5,
+            face: 2,
+            size: 0,
+            look: [0x1000, 0x2000, 0x3000, 0x4000, 0x5000, 0x6000, 0x7000],
+        };
+
+        let packet = char_list("Nameless", &[character]);
+        assert_eq!(packet.len(), CHAR_LIST_LEN);
+        assert_eq!(packet[8], LobbyCommand::CharListReply as u8);
+        assert_eq!(packet[28], 1);
+
+        let entry = &packet[32..32 + CHAR_ENTRY_LEN];
+        assert_eq!(&entry[0..4], &21828u32.to_le_bytes());
+        assert_eq!(&entry[4..8], &21828u32.to_le_bytes());
+        assert_eq!(&entry[12..22], b"Shantotto\0");
+        assert_eq!(&entry[28..37], b"Nameless\0");
+        assert_eq!(entry[44], 5);
+        assert_eq!(entry[46], 4);
+        assert_eq!(&entry[58..60], &0x1000u16.to_le_bytes());
+        assert_eq!(&entry[70..72], &0x7000u16.to_le_bytes());
+        assert_eq!(entry[73], 75);
+        assert_eq!(&entry[78..80], &238u16.to_le_bytes());
+
+        assert!(packet[32 + CHAR_ENTRY_LEN..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn it_encodes_version_reply() {
+        let packet = version_reply(0x07FE);
+        assert_eq!(packet.len(), VERSION_REPLY_LEN);
+        assert_eq!(packet[8], LobbyCommand::VersionReply as u8);
+        assert_eq!(&packet[32..36], &0x07FEu32.to_le_bytes());
+    }
+
+    #[test]
+    fn it_encodes_world_list() {
+        let packet = world_list("Nameless");
+        assert_eq!(packet.len(), WORLD_LIST_LEN);
+        assert_eq!(&packet[36..45], b"Nameless\0");
+    }
+
+    #[test]
+    fn it_bounds_checks_fields() {
+        let packet = [0; 34];
+        assert!(read_u32(&packet, SELECT_CHAR_ID).is_ok());
+        assert!(read_u32(&packet, DELETE_CHAR_ID).is_err());
+        assert!(byte(&packet, CREATE_FACE).is_err());
+    }
+
+    #[tokio::test]
+    async fn it_refuses_commands_until_the_version_is_accepted() {
+        let (ctx, writer, mut replies) = lobby(Default::default());
+        session(&ctx, |session| session.version_accepted = false);
+        let version_mismatch = Some(LobbyError::VersionMismatch as u16);
+
+        send(&ctx, &writer, reserve("Ajido")).await;
+        assert_eq!(
+            reply_error(replies.recv().await.unwrap()),
+            version_mismatch
+        );
+
+        send(&ctx, &writer, version("30221205_0")).await;
+        assert_eq!(
+            reply_error(replies.recv().await.unwrap()),
+            version_mismatch
+        );
+
+        send(&ctx, &writer, reserve("Ajido")).await;
+        assert_eq!(
+            reply_error(replies.recv().await.unwrap()),
+            version_mismatch
+        );
+
+        send(&ctx, &writer, version("30221206_0")).await;
+        let reply = replies.recv().await.unwrap();
+        assert_eq!(reply[8], LobbyCommand::VersionReply as u8);
+        assert!(!session(&ctx, |session| session.version_mismatch));
+
+        send(&ctx, &writer, reserve("Ajido")).await;
+        assert_eq!(reply_error(replies.recv().await.unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn it_accepts_any_version_without_a_version_lock() {
+        let (mut ctx, writer, mut replies) = lobby(Default::default());
+        ctx.config.ver_lock = VersionLock::Disabled;
+        session(&ctx, |session| session.version_accepted = false);
+
+        send(&ctx, &writer, version("unknown")).await;
+        let reply = replies.recv().await.unwrap();
+        assert_eq!(reply[8], LobbyCommand::VersionReply as u8);
+        assert!(session(&ctx, |session| session.version_mismatch));
+
+        send(&ctx, &writer, reserve("Ajido")).await;
+        assert_eq!(reply_error(replies.recv().await.unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn it_reserves_names_and_creates_characters() {
+        let store = Arc::new(MemoryStore::default());
+        let (mut ctx, writer, mut replies) = lobby(store.clone());
+        ctx.config.disable_mob_npc_char_names = true;
+        store.add_mob_or_npc_name("Maat");
+
+        let character = test_character();
+        let name_unavailable = Some(LobbyError::NameUnavailable as u16);
+
+        send(&ctx, &writer, reserve("Maat")).await;
+        assert_eq!(
+            reply_error(replies.recv().await.unwrap()),
+            name_unavailable
+        );
+
+        send(&ctx, &writer, reserve(&character.name)).await;
+        assert_eq!(reply_error(replies.recv().await.unwrap()), None);
+
+        send(&ctx, &writer, create()).await;
+        assert_eq!(reply_error(replies.recv().await.unwrap()), None);
+
+        let characters = store.list_characters(ACC_ID).await.unwrap();
+        assert_eq!(characters.len(), 1);
+        assert_eq!(characters[0].name, character.name);
+        assert_eq!(characters[0].race, character.race);
+        assert_eq!(characters[0].main_job, character.main_job);
+
+        // The reservation is used up by the creation.
+        send(&ctx, &writer, create()).await;
+        assert_eq!(
+            reply_error(replies.recv().await.unwrap()),
+            name_unavailable
+        );
+
+        send(&ctx, &writer, reserve(&character.name)).await;
+        assert_eq!(
+            reply_error(replies.recv().await.unwrap()),
+            name_unavailable
+        );
+    }
+
+    #[tokio::test]
+    async fn it_refuses_characters_the_lobby_does_not_offer() {
+        let store = Arc::new(MemoryStore::default());
+        let (ctx, writer, mut replies) = lobby(store.clone());
+        let unavailable = Some(LobbyError::Unavailable as u16);
+
+        send(&ctx, &writer, reserve(&test_character().name)).await;
+        assert_eq!(reply_error(replies.recv().await.unwrap()), None);
+
+        for (offset, value) in [
+            (CREATE_RACE, 200),
+            (CREATE_NATION, 3),
+            (CREATE_MAIN_JOB, 32),
+            (CREATE_SIZE, 3),
+            (CREATE_FACE, 16),
+        ] {
+            let mut packet = create();
+            packet[offset] = value;
+
+            send(&ctx, &writer, packet).await;
+            assert_eq!(reply_error(replies.recv().await.unwrap()), unavailable);
+        }
+        assert!(store.list_characters(ACC_ID).await.unwrap().is_empty());
+
+        // The reservation is still there for a valid creation.
+        send(&ctx, &writer, create()).await;
+        assert_eq!(reply_error(replies.recv().await.unwrap()), None);
+        assert_eq!(store.list_characters(ACC_ID).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_deletes_characters() {
+        let store = Arc::new(MemoryStore::default());
+        let (ctx, writer, mut replies) = lobby(store.clone());
+        let char_id = store
+            .create_character(
+                &ctx.config.new_character,
+                ACC_ID,
+                &test_character(),
+            )
+            .await
+            .unwrap();
+
+        let mut packet = LobbyPacket::new(LobbyCommand::Delete, 0x24);
+        packet[DELETE_CHAR_ID].copy_from_slice(&char_id.to_le_bytes());
+
+        send(&ctx, &writer, packet.clone()).await;
+        assert_eq!(reply_error(replies.recv().await.unwrap()), None);
+        assert!(store.list_characters(ACC_ID).await.unwrap().is_empty());
+
+        send(&ctx, &writer, packet).await;
+        assert_eq!(
+            reply_error(replies.recv().await.unwrap()),
+            Some(LobbyError::Unavailable as u16)
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use crate::characters::StartConfig;
+use crate::client_version::{ClientVersion, VersionLock};
+use crate::settings::Settings;
+
+/// Login settings needed by connection handlers.
+///
+/// `Settings` borrows the lua state and cannot be shared across tasks, so the
+/// values are read once at startup.
+#[derive(Clone, Debug)]
+pub struct LoginConfig {
+    pub account_creation: bool,
+    pub banned_words: Vec<String>,
+    pub disable_mob_npc_char_names: bool,
+    pub existing_session: ExistingSession,
+    /// Simultaneous sessions per IP, 0 for no limit.
+    pub login_limit: u32,
+    pub client_ver: ClientVersion,
+    pub ver_lock: VersionLock,
+    pub log_user_ip: bool,
+    /// `login.OPTIMIZE_TABLES`, run at startup and then every
+    /// `optimize_interval`, if set.
+    pub optimize_tables: bool,
+    pub optimize_interval: Option<Duration>,
+    pub character_deletion: bool,
+    /// `main.SERVER_NAME`, shown as the world name in the lobby.
+    pub server_name: String,
+    /// Expansions enabled in `main`, as the bitmask the client expects.
+    pub expansions: u32,
+    pub new_character: StartConfig,
+}
+
+/// `main.ENABLE_*` settings, by their bit in the expansion mask. Rise of
+/// Zilart has no setting and is always enabled.
+const EXPANSIONS: [(&str, u32); 9] = [
+    ("main.ENABLE_COP", 0x0004),
+    ("main.ENABLE_TOAU", 0x0008),
+    ("main.ENABLE_WOTG", 0x0010),
+    ("main.ENABLE_ACP", 0x0020),
+    ("main.ENABLE_AMK", 0x0040),
+    ("main.ENABLE_ASA", 0x0080),
+    ("main.ENABLE_ABYSSEA", 0x0100),
+    ("main.ENABLE_SOA", 0x0200),
+    ("main.ENABLE_ROV", 0x0400),
+];
+
+const EXPANSION_ROZ: u32 = 0x0002;
+
+/// What to do when an account logs in while one of its characters is still
+/// in `accounts_sessions`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExistingSession {
+    /// Reject the new login.
+    Refuse,
+    /// Remove the existing session from `accounts_sessions` and let the new
+    /// login through. This does not disconnect the character in game.
+    Release,
+    /// Let the new login through and leave the existing session alone.
+    Allow,
+}
+
+impl ExistingSession {
+    fn from_str(s: &str) -> Result<ExistingSession> {
+        match s {
+            "refuse" => Ok(ExistingSession::Refuse),
+            "release" => Ok(ExistingSession::Release),
+            "allow" => Ok(ExistingSession::Allow),
+            _ => bail!("Invalid login.EXISTING_SESSION: {}", s),
+        }
+    }
+}
+
+impl LoginConfig {
+    pub fn from_settings(settings: &Settings) -> Result<LoginConfig> {
+        Ok(LoginConfig {
+            account_creation: settings
+                .try_get::<bool>("login.ACCOUNT_CREATION")?,
+            banned_words: settings.try_get_list("login.BANNED_WORDS_LIST")?,
+            disable_mob_npc_char_names: settings
+                .try_get::<bool>("login.DISABLE_MOB_NPC_CHAR_NAMES")?,
+            existing_session: ExistingSession::from_str(
+                &settings.try_get::<String>("login.EXISTING_SESSION")?,
+            )?,
+            login_limit: settings.try_get::<u32>("login.LOGIN_LIMIT")?,
+            client_ver: settings
+                .try_get::<String>("login.CLIENT_VER")?
+                .parse()?,
+            ver_lock: VersionLock::from_setting(
+                settings.try_get::<u8>("login.VER_LOCK")?,
+            )?,
+            log_user_ip: settings.try_get::<bool>("login.LOG_USER_IP")?,
+            optimize_tables: settings
+                .try_get::<bool>("login.OPTIMIZE_TABLES")?,
+            optimize_interval: optimize_interval(
+                settings.try_get::<u64>("login.OPTIMIZE_TABLES_INTERVAL")?,
+            )?,
+            character_deletion: settings
+                .try_get::<bool>("login.CHARACTER_DELETION")?,
+            server_name: settings.try_get::<String>("main.SERVER_NAME")?,
+            expansions: expansions(settings)?,
+            new_character: StartConfig::from_settings(settings)?,
+        })
+    }
+
+    /// Whether a client reporting `version` may enter the lobby. Versions
+    /// that cannot be parsed are only refused while `VER_LOCK` is on.
+    pub fn allows_client(&self, version: Option<ClientVersion>) -> bool {
+        match version {
+            Some(version) => self.ver_lock.allows(self.client_ver, version),
+            None => self.ver_lock == VersionLock::Disabled,
+        }
+    }
+}
+
+/// `login.OPTIMIZE_TABLES_INTERVAL`, in hours, 0 for no interval.
+fn optimize_interval(hours: u64) -> Result<Option<Duration>> {
+    match hours {
+        0 => Ok(None),
+        hours => match hours.checked_mul(60 * 60) {
+            Some(secs) => Ok(Some(Duration::from_secs(secs))),
+            None => bail!("Invalid login.OPTIMIZE_TABLES_INTERVAL: {}", hours),
+        },
+    }
+}
+
+fn expansions(settings: &Settings) -> Result<u32> {
+    let mut expansions = EXPANSION_ROZ;
+
+    for (key, bit) in EXPANSIONS {
+        if settings.try_get::<u8>(key)? != 0 {
+            expansions |= bit;
+        }
+    }
+
+    Ok(expansions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::Lua;
+
+    #[test]
+    fn it_loads_default_login_config() {
+        let lua = Lua::new().unwrap();
+        let settings = Settings::new(&lua).unwrap();
+        let config = LoginConfig::from_settings(&settings).unwrap();
+
+        assert!(config.account_creation);
+        assert_eq!(config.banned_words, vec!["badword".to_owned()]);
+        assert!(!config.disable_mob_npc_char_names);
+        assert_eq!(config.existing_session, ExistingSession::Refuse);
+        assert_eq!(config.login_limit, 0);
+        assert_eq!(config.client_ver, "30221206_0".parse().unwrap());
+        assert_eq!(config.ver_lock, VersionLock::AtLeast);
+        assert!(!config.log_user_ip);
+        assert!(!config.optimize_tables);
+        assert_eq!(config.optimize_interval, None);
+        assert!(config.character_deletion);
+        assert_eq!(config.server_name, "Nameless");
+        assert_eq!(config.expansions, 0x07FE);
+    }
+
+    #[test]
+    fn it_parses_optimize_interval() {
+        assert_eq!(optimize_interval(0).unwrap(), None);
+        assert_eq!(
+            optimize_interval(24).unwrap(),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        assert!(optimize_interval(u64::MAX / 60).is_err());
+    }
+
+    #[test]
+    fn it_parses_existing_session() {
+        assert_eq!(
+            ExistingSession::from_str("refuse").unwrap(),
+            ExistingSession::Refuse
+        );
+        assert_eq!(
+            ExistingSession::from_str("release").unwrap(),
+            ExistingSession::Release
+        );
+        assert!(ExistingSession::from_str("ignore").is_err());
+        assert!(ExistingSession::from_str("kick").is_err());
+    }
+
+    #[test]
+    fn it_only_checks_client_versions_when_locked() {
+        let lua = Lua::new().unwrap();
+        let settings = Settings::new(&lua).unwrap();
+        let mut config = LoginConfig::from_settings(&settings).unwrap();
+        let old = "30221205_0".parse().ok();
+
+        assert!(config.allows_client(Some(config.client_ver)));
+        assert!(!config.allows_client(old));
+        assert!(!config.allows_client(None));
+
+        config.ver_lock = VersionLock::Disabled;
+        assert!(config.allows_client(old));
+        assert!(config.allows_client(None));
+    }
+}
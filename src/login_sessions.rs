@@ -1,32 +1,447 @@
-use std::collections::LinkedList;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 
+use crate::connect_history::{Clock, SystemClock};
+
+/// Queue of bytes to send on one of the lobby connections of a session.
+pub type LobbySocket = mpsc::UnboundedSender<Vec<u8>>;
+
+/// How long a session may go unused before `expire` drops it, unless its
+/// data connection is still open.
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Lobby connections that belong to a session once the client has logged in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LobbyConnection {
+    Data,
+    View,
+    Conf,
+}
+
+/// Sessions of the accounts that have logged in, shared by every listener.
+///
+/// Sessions are indexed by account and by client address. The lobby
+/// connections come from other ports than the login, so the port is kept
+/// with the session but not indexed.
 pub struct LoginSessions {
-    list: LinkedList<LoginSessionData>,
+    clock: Arc<dyn Clock>,
+    registry: Mutex<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    by_account: HashMap<u32, LoginSessionData>,
+    /// Accounts by the address they logged in from, oldest login first.
+    by_addr: HashMap<IpAddr, Vec<u32>>,
+}
+
+impl Registry {
+    fn remove(&mut self, acc_id: u32) -> Option<LoginSessionData> {
+        let session = self.by_account.remove(&acc_id)?;
+        let addr = session.client.ip();
+
+        if let Some(accounts) = self.by_addr.get_mut(&addr) {
+            accounts.retain(|&other| other != acc_id);
+            if accounts.is_empty() {
+                self.by_addr.remove(&addr);
+            }
+        }
+
+        Some(session)
+    }
 }
 
 impl LoginSessions {
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
-            list: LinkedList::new(),
+            clock,
+            registry: Mutex::new(Registry::default()),
+        }
+    }
+
+    /// Adds a session, replacing any previous session of the same account.
+    pub fn insert(&self, mut session: LoginSessionData) {
+        let mut registry = self.registry.lock().unwrap();
+
+        registry.remove(session.acc_id);
+
+        session.last_active = self.clock.now();
+        registry
+            .by_addr
+            .entry(session.client.ip())
+            .or_default()
+            .push(session.acc_id);
+        registry.by_account.insert(session.acc_id, session);
+    }
+
+    /// Runs `f` on the session of `acc_id`, if it was created by a login from
+    /// `addr`.
+    pub fn update<R>(
+        &self,
+        acc_id: u32,
+        addr: IpAddr,
+        f: impl FnOnce(&mut LoginSessionData) -> R,
+    ) -> Option<R> {
+        let mut registry = self.registry.lock().unwrap();
+
+        let session = registry
+            .by_account
+            .get_mut(&acc_id)
+            .filter(|session| session.client.ip() == addr.to_canonical())?;
+
+        session.last_active = self.clock.now();
+        Some(f(session))
+    }
+
+    /// Runs `f` on the latest session created by a login from `addr`. Used
+    /// for connections that do not say which account they belong to.
+    pub fn update_by_client<R>(
+        &self,
+        addr: IpAddr,
+        f: impl FnOnce(&mut LoginSessionData) -> R,
+    ) -> Option<R> {
+        let mut registry = self.registry.lock().unwrap();
+
+        let acc_id = *registry.by_addr.get(&addr.to_canonical())?.last()?;
+        let session = registry.by_account.get_mut(&acc_id)?;
+
+        session.last_active = self.clock.now();
+        Some(f(session))
+    }
+
+    /// Links a lobby connection to the session of `acc_id`. Returns whether
+    /// there was such a session from `addr`.
+    pub fn attach(
+        &self,
+        acc_id: u32,
+        addr: IpAddr,
+        connection: LobbyConnection,
+        socket: LobbySocket,
+    ) -> bool {
+        self.update(acc_id, addr, |session| {
+            *session.socket_mut(connection) = Some(socket);
+        })
+        .is_some()
+    }
+
+    /// Links a lobby connection to the latest session from `addr` and
+    /// returns its account.
+    pub fn attach_by_client(
+        &self,
+        addr: IpAddr,
+        connection: LobbyConnection,
+        socket: LobbySocket,
+    ) -> Option<u32> {
+        self.update_by_client(addr, |session| {
+            *session.socket_mut(connection) = Some(socket);
+            session.acc_id
+        })
+    }
+
+    pub fn detach(&self, acc_id: u32, connection: LobbyConnection) {
+        let mut registry = self.registry.lock().unwrap();
+
+        if let Some(session) = registry.by_account.get_mut(&acc_id) {
+            *session.socket_mut(connection) = None;
         }
     }
+
+    pub fn remove(&self, acc_id: u32) -> Option<LoginSessionData> {
+        self.registry.lock().unwrap().remove(acc_id)
+    }
+
+    /// Drops the sessions that have not been used for `timeout` and have no
+    /// open data connection, such as those of clients that never made it to
+    /// the lobby.
+    pub fn expire(&self, timeout: Duration) -> Vec<LoginSessionData> {
+        let now = self.clock.now();
+        let mut registry = self.registry.lock().unwrap();
+
+        let expired: Vec<u32> = registry
+            .by_account
+            .values()
+            .filter(|session| {
+                now.saturating_duration_since(session.last_active) >= timeout
+                    && !session.is_connected(LobbyConnection::Data)
+            })
+            .map(|session| session.acc_id)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|acc_id| registry.remove(acc_id))
+            .collect()
+    }
+
+    /// Ids of the accounts with a session from `addr`.
+    pub fn accounts_from(&self, addr: IpAddr) -> Vec<u32> {
+        self.registry
+            .lock()
+            .unwrap()
+            .by_addr
+            .get(&addr.to_canonical())
+            .cloned()
+            .unwrap_or_default()
+    }
 }
 
 pub struct LoginSessionData {
-    login: [u8; 16],
-    acc_id: u32,
-    service_d: u32,
-    client_addr: u32,
-    client_port: u16,
-    serv_ip: u32,
-
-    char_name: [u8; 15],
-    login_socket: TcpStream,
-    login_lobbydata_socket: TcpStream,
-    login_lobbyview_socket: TcpStream,
-    login_lobbyconf_socket: TcpStream,
-
-    just_created_new_char: bool,
+    login: String,
+    pub acc_id: u32,
+    client: SocketAddr,
+    last_active: Instant,
+
+    pub login_lobbydata_socket: Option<LobbySocket>,
+    pub login_lobbyview_socket: Option<LobbySocket>,
+    pub login_lobbyconf_socket: Option<LobbySocket>,
+
+    /// Name reserved for the character being created.
+    pub char_name: Option<String>,
+    /// Character picked in the lobby, waiting for the data connection to
+    /// hand it off to its zone server.
+    pub selected_char: Option<u32>,
+    /// Whether the view connection reported a version `login.VER_LOCK`
+    /// allows. Nothing else is served in the lobby until it has.
+    pub version_accepted: bool,
+    /// Whether the client reported a version other than `login.CLIENT_VER`.
+    pub version_mismatch: bool,
+    pub just_created_new_char: bool,
+}
+
+impl LoginSessionData {
+    pub fn new(acc_id: u32, login: &str, client: SocketAddr) -> Self {
+        Self {
+            login: login.to_owned(),
+            acc_id,
+            client: SocketAddr::new(client.ip().to_canonical(), client.port()),
+            last_active: Instant::now(),
+            login_lobbydata_socket: None,
+            login_lobbyview_socket: None,
+            login_lobbyconf_socket: None,
+            char_name: None,
+            selected_char: None,
+            version_accepted: false,
+            version_mismatch: false,
+            just_created_new_char: false,
+        }
+    }
+
+    pub fn login(&self) -> &str {
+        &self.login
+    }
+
+    /// Address and port the account logged in from.
+    pub fn client(&self) -> SocketAddr {
+        self.client
+    }
+
+    fn socket_mut(
+        &mut self,
+        connection: LobbyConnection,
+    ) -> &mut Option<LobbySocket> {
+        match connection {
+            LobbyConnection::Data => &mut self.login_lobbydata_socket,
+            LobbyConnection::View => &mut self.login_lobbyview_socket,
+            LobbyConnection::Conf => &mut self.login_lobbyconf_socket,
+        }
+    }
+
+    fn is_connected(&self, connection: LobbyConnection) -> bool {
+        let socket = match connection {
+            LobbyConnection::Data => &self.login_lobbydata_socket,
+            LobbyConnection::View => &self.login_lobbyview_socket,
+            LobbyConnection::Conf => &self.login_lobbyconf_socket,
+        };
+
+        socket.as_ref().is_some_and(|socket| !socket.is_closed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connect_history::ManualClock;
+
+    fn sessions() -> (LoginSessions, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::default());
+
+        (LoginSessions::with_clock(clock.clone()), clock)
+    }
+
+    fn client(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn addr(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn it_updates_sessions_by_account_and_address() {
+        let sessions = LoginSessions::new();
+        sessions.insert(LoginSessionData::new(1000, "a", client("10.0.0.1:1")));
+
+        assert_eq!(sessions.update(1000, addr("10.0.0.2"), |_| ()), None);
+        assert_eq!(sessions.update(1001, addr("10.0.0.1"), |_| ()), None);
+        assert_eq!(
+            sessions.update(1000, addr("10.0.0.1"), |session| session.acc_id),
+            Some(1000)
+        );
+
+        let removed = sessions.remove(1000).unwrap();
+        assert_eq!(removed.login(), "a");
+        assert_eq!(removed.client(), client("10.0.0.1:1"));
+        assert_eq!(sessions.update(1000, addr("10.0.0.1"), |_| ()), None);
+        assert!(sessions.accounts_from(addr("10.0.0.1")).is_empty());
+    }
+
+    #[test]
+    fn it_finds_latest_session_by_address() {
+        let sessions = LoginSessions::new();
+        sessions.insert(LoginSessionData::new(1000, "a", client("10.0.0.1:1")));
+        sessions.insert(LoginSessionData::new(1001, "b", client("10.0.0.1:2")));
+
+        assert_eq!(
+            sessions.update_by_client(addr("::ffff:10.0.0.1"), |session| {
+                session.acc_id
+            }),
+            Some(1001)
+        );
+        assert_eq!(sessions.update_by_client(addr("10.0.0.2"), |_| ()), None);
+
+        sessions.remove(1001);
+        assert_eq!(
+            sessions.update_by_client(addr("10.0.0.1"), |session| {
+                session.acc_id
+            }),
+            Some(1000)
+        );
+    }
+
+    #[test]
+    fn it_attaches_lobby_connections() {
+        let sessions = LoginSessions::new();
+        sessions.insert(LoginSessionData::new(1000, "a", client("10.0.0.1:1")));
+
+        let (data, _data_rx) = mpsc::unbounded_channel();
+        let (view, _view_rx) = mpsc::unbounded_channel();
+
+        assert!(!sessions.attach(
+            1000,
+            addr("10.0.0.2"),
+            LobbyConnection::Data,
+            data.clone()
+        ));
+        assert!(sessions.attach(
+            1000,
+            addr("10.0.0.1"),
+            LobbyConnection::Data,
+            data
+        ));
+        assert_eq!(
+            sessions.attach_by_client(
+                addr("10.0.0.1"),
+                LobbyConnection::View,
+                view
+            ),
+            Some(1000)
+        );
+
+        let connected = |connection| {
+            sessions
+                .update(1000, addr("10.0.0.1"), |session| {
+                    session.is_connected(connection)
+                })
+                .unwrap()
+        };
+        assert!(connected(LobbyConnection::Data));
+        assert!(connected(LobbyConnection::View));
+        assert!(!connected(LobbyConnection::Conf));
+
+        sessions.detach(1000, LobbyConnection::View);
+        assert!(!connected(LobbyConnection::View));
+    }
+
+    #[test]
+    fn it_expires_idle_sessions() {
+        let (sessions, clock) = sessions();
+        sessions.insert(LoginSessionData::new(1000, "a", client("10.0.0.1:1")));
+        sessions.insert(LoginSessionData::new(1001, "b", client("10.0.0.2:1")));
+        sessions.insert(LoginSessionData::new(1002, "c", client("10.0.0.3:1")));
+
+        let (data, data_rx) = mpsc::unbounded_channel();
+        sessions.attach(1002, addr("10.0.0.3"), LobbyConnection::Data, data);
+
+        clock.advance(Duration::from_secs(30));
+        sessions.update(1001, addr("10.0.0.2"), |_| ());
+        clock.advance(Duration::from_secs(40));
+
+        let expired = sessions.expire(Duration::from_secs(60));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].acc_id, 1000);
+        assert!(sessions.accounts_from(addr("10.0.0.1")).is_empty());
+
+        // Once its data connection has gone away, a session can expire too.
+        drop(data_rx);
+        clock.advance(Duration::from_secs(60));
+
+        let mut expired: Vec<u32> = sessions
+            .expire(Duration::from_secs(60))
+            .iter()
+            .map(|session| session.acc_id)
+            .collect();
+        expired.sort();
+        assert_eq!(expired, vec![1001, 1002]);
+    }
+
+    #[test]
+    fn it_counts_accounts_by_address() {
+        let sessions = LoginSessions::new();
+        sessions.insert(LoginSessionData::new(1000, "a", client("10.0.0.1:1")));
+        sessions.insert(LoginSessionData::new(1001, "b", client("10.0.0.1:2")));
+        sessions.insert(LoginSessionData::new(1002, "c", client("10.0.0.2:1")));
+        sessions.insert(LoginSessionData::new(1000, "a", client("10.0.0.1:3")));
+
+        let mut accounts = sessions.accounts_from(addr("10.0.0.1"));
+        accounts.sort();
+        assert_eq!(accounts, vec![1000, 1001]);
+
+        assert_eq!(sessions.accounts_from(addr("::ffff:10.0.0.2")), vec![1002]);
+    }
+
+    #[test]
+    fn it_is_shared_between_threads() {
+        let sessions = Arc::new(LoginSessions::new());
+
+        let threads: Vec<_> = (0..8u8)
+            .map(|i| {
+                let sessions = sessions.clone();
+                std::thread::spawn(move || {
+                    let client = SocketAddr::from(([10, 0, 0, i], 1));
+                    for j in 0..100 {
+                        let acc_id = u32::from(i) * 100 + j;
+                        sessions
+                            .insert(LoginSessionData::new(acc_id, "a", client));
+                        assert!(sessions
+                            .update(acc_id, client.ip(), |_| ())
+                            .is_some());
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(sessions.accounts_from(addr("10.0.0.3")).len(), 100);
+    }
 }
@@ -1,41 +1,80 @@
+mod bans;
+mod characters;
+mod client_version;
+mod connect_history;
 mod db;
+mod ip_log;
+mod lobby;
+mod lobby_conf;
+mod lobby_data;
+mod lobby_view;
 mod logging;
+mod login_config;
 mod login_sessions;
 mod lua;
+mod maintenance;
+mod migrations;
+mod packets;
+mod password;
 mod repl;
 mod server_timer;
 mod settings;
 mod socket;
+mod store;
+mod validation;
 
 use std::env::current_dir;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
-use mysql_async::{prelude::*, Pool};
+use mysql_async::Pool;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::AsyncWriteExt,
     net::{TcpListener, TcpStream},
 };
 
-use clap::Parser;
-use env_logger;
+use clap::{Parser, Subcommand};
+use login_config::{ExistingSession, LoginConfig};
+use login_sessions::{LoginSessionData, LoginSessions, SESSION_TIMEOUT};
+use packets::login::{self, LoginReply, LoginRequest, REQUEST_LEN};
+use password::Verification;
 use server_timer::ServerTimer;
 use settings::Settings;
-use spdlog::prelude::*;
-
-const LOGIN_ERROR: u8 = 0x02;
-const LOGIN_ATTEMPT: u8 = 0x10;
-const LOGIN_CREATE: u8 = 0x20;
-const LOGIN_CHANGE_PASSWORD: u8 = 0x30;
+use socket::Socket;
+use spdlog::{prelude::*, Logger};
+use store::{Account, AccountStore, CharacterStore, GameSession, MySqlStore};
+use validation::validate_account;
 
 const ACCOUNT_STATUS_CODE_NORMAL: u32 = 0x01;
 const ACCOUNT_STATUS_CODE_BANNED: u32 = 0x02;
 
+const ACCOUNT_PRIVILEGE_CODE_USER: u32 = 0x01;
+const ACCOUNT_PRIVILEGE_CODE_ADMIN: u32 = 0x02;
+
+/// Account ids below this value are reserved.
+const ACCOUNT_ID_MIN: u32 = 1000;
+
 #[derive(Parser)]
 struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
     log: Option<std::path::PathBuf>,
     append_date: Option<bool>,
 }
 
+#[derive(Subcommand, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    /// Creates or upgrades the database schema, then exits.
+    ///
+    /// Map server tables missing from the database are created as stubs
+    /// that are only fit for development. Import the LandSandBoat SQL files
+    /// first on a database the map server is going to use.
+    Migrate,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli_args = CliArgs::parse();
@@ -56,118 +95,769 @@ async fn main() -> Result<()> {
     let timer = ServerTimer::new();
     let lua = lua::Lua::new()?;
     let settings = Settings::new(&lua)?;
-    let pool = db::create_pool(builder, &settings).await?;
-    let login_sessions = login_sessions::LoginSessions::new();
+    let pool = db::create_pool(builder.clone(), &settings).await?;
 
-    r#"OPTIMIZE TABLE `accounts`,`accounts_banned`, 
-        `accounts_sessions`, `chars`,`char_equip`, `char_inventory`, 
-        `char_jobs`,`char_look`,`char_stats`, `char_vars`, `char_bazaar_msg`,
-        `char_skills`, `char_titles`, `char_effects`, `char_exp`"#
-        .ignore(pool)
-        .await?;
+    if cli_args.command == Some(Command::Migrate) {
+        return migrations::run(&pool, &logger).await;
+    }
+    migrations::check(&pool, &logger).await?;
 
-    if !settings.try_get::<bool>("login.ACCOUNT_CREATION")? {
+    let config = LoginConfig::from_settings(&settings)?;
+    let socket = socket::socket_init_tcp(builder, &settings)?;
+
+    if !config.account_creation {
         info!(
             logger: logger,
             "New account creation is currently disabled."
         );
     }
 
-    if !settings.try_get::<bool>("login.CHARACTER_DELETION")? {
+    if settings.try_get::<u8>("login.MAINT_MODE")? != 0 {
+        info!(
+            logger: logger,
+            "Maintenance mode is enabled, only GM accounts can log in."
+        );
+    }
+
+    if !config.character_deletion {
         info!(logger: logger, "Character deletion is currently disabled.");
     }
 
-    do_init(&settings).await?;
+    let listener = socket::bind(
+        &settings.try_get::<String>("network.LOGIN_AUTH_IP")?,
+        settings.try_get::<u16>("network.LOGIN_AUTH_PORT")?,
+    )
+    .await?;
 
-    Ok(())
-}
+    let data_listener = socket::bind(
+        &settings.try_get::<String>("network.LOGIN_DATA_IP")?,
+        settings.try_get::<u16>("network.LOGIN_DATA_PORT")?,
+    )
+    .await?;
 
-async fn do_init<'lua>(settings: &Settings<'lua>) -> Result<()> {
-    let listener = TcpListener::bind(format!(
-        "{}:{}",
-        settings.try_get::<String>("network.LOGIN_AUTH_IP")?,
-        settings.try_get::<u16>("network.LOGIN_AUTH_PORT")?
-    ))
+    let view_listener = socket::bind(
+        &settings.try_get::<String>("network.LOGIN_VIEW_IP")?,
+        settings.try_get::<u16>("network.LOGIN_VIEW_PORT")?,
+    )
     .await?;
 
-    loop {
-        let (mut socket, addr) = listener.accept().await?;
+    let conf_listener = socket::bind(
+        &settings.try_get::<String>("network.LOGIN_CONF_IP")?,
+        settings.try_get::<u16>("network.LOGIN_CONF_PORT")?,
+    )
+    .await?;
 
-        if let Err(err) = handle(&mut socket).await {
-            println!("Error: {:?}", err);
+    let store = Arc::new(MySqlStore::new(pool.clone()));
+
+    let ctx = Arc::new(LoginContext {
+        pool,
+        accounts: store.clone(),
+        characters: store,
+        config,
+        socket: Arc::new(socket),
+        sessions: LoginSessions::new(),
+        maint_mode: AtomicBool::new(
+            settings.try_get::<u8>("login.MAINT_MODE")? != 0,
+        ),
+        search_port: settings.try_get::<u16>("network.SEARCH_PORT")?,
+        logger,
+    });
+
+    let expiry_ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+
+            for session in expiry_ctx.sessions.expire(SESSION_TIMEOUT) {
+                info!(
+                    logger: expiry_ctx.logger,
+                    "Login session of {} from {} expired",
+                    session.login(),
+                    session.client()
+                );
+            }
         }
+    });
+
+    if ctx.config.optimize_tables {
+        let optimize_ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                maintenance::optimize_tables(
+                    &optimize_ctx.pool,
+                    &optimize_ctx.logger,
+                )
+                .await;
+
+                let Some(interval) = optimize_ctx.config.optimize_interval
+                else {
+                    break;
+                };
+                tokio::time::sleep(interval).await;
+            }
+        });
     }
+
+    let repl_ctx = ctx.clone();
+    tokio::spawn(async move {
+        if let Err(err) = repl::run(repl_ctx.clone()).await {
+            error!(logger: repl_ctx.logger, "Admin console stopped: {:?}", err);
+        }
+    });
+
+    tokio::try_join!(
+        do_init(listener, ctx.clone()),
+        lobby_data::run(data_listener, ctx.clone()),
+        lobby_view::run(view_listener, ctx.clone()),
+        lobby_conf::run(conf_listener, ctx),
+    )?;
+
+    Ok(())
 }
 
-async fn handle(socket: &mut TcpStream) -> Result<()> {
-    let mut buffer: [u8; 33] = [0; 33];
-    socket.read_exact(&mut buffer).await?;
+/// State shared by every connection handler.
+struct LoginContext {
+    /// For the tables that only the login server uses. Accounts and
+    /// characters go through their stores.
+    pool: Pool,
+    accounts: Arc<dyn AccountStore>,
+    characters: Arc<dyn CharacterStore>,
+    config: LoginConfig,
+    socket: Arc<Socket>,
+    sessions: LoginSessions,
+    /// `login.MAINT_MODE`, which can be toggled from the admin console.
+    maint_mode: AtomicBool,
+    /// `network.SEARCH_PORT`, handed to characters entering the world.
+    search_port: u16,
+    logger: Logger,
+}
 
-    let name = std::str::from_utf8(&buffer[0..16]).ok();
-    let password = std::str::from_utf8(&buffer[16..32]).ok();
-    let code = buffer[32];
+impl LoginContext {
+    fn maint_mode(&self) -> bool {
+        self.maint_mode.load(Ordering::Relaxed)
+    }
 
-    if let (Some(name), Some(password)) = (name, password) {
-        process(code, name, password);
-    } else {
-        socket.write(&[LOGIN_ERROR]).await?;
+    fn set_maint_mode(&self, enable: bool) {
+        self.maint_mode.store(enable, Ordering::Relaxed);
     }
+}
+
+async fn do_init(listener: TcpListener, ctx: Arc<LoginContext>) -> Result<()> {
+    socket::serve(listener, ctx.socket.clone(), move |stream, addr| {
+        let ctx = ctx.clone();
+        async move { handle(stream, addr, &ctx).await }
+    })
+    .await
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    client: SocketAddr,
+    ctx: &LoginContext,
+) -> Result<()> {
+    let mut buffer = vec![0; REQUEST_LEN];
+    ctx.socket.read_exact(&mut stream, &mut buffer).await?;
+
+    // A change password request carries the new password after the opcode.
+    buffer.resize(login::request_len(&buffer), 0);
+    if buffer.len() > REQUEST_LEN {
+        ctx.socket
+            .read_exact(&mut stream, &mut buffer[REQUEST_LEN..])
+            .await?;
+    }
+
+    let reply = match LoginRequest::decode(&buffer) {
+        Ok(request) => {
+            process(ctx, client, request).await.unwrap_or_else(|err| {
+                error!(logger: ctx.logger, "Login request failed: {:?}", err);
+                LoginReply::Error
+            })
+        }
+        Err(err) => {
+            debug!(
+                logger: ctx.logger,
+                "Invalid login request from {}: {}", client, err
+            );
+            LoginReply::Error
+        }
+    };
+
+    stream.write_all(&reply.encode()).await?;
 
     Ok(())
 }
 
-fn process(code: u8, name: &str, password: &str) {
-    match code {
-        LOGIN_ATTEMPT => {}
-        _ => {}
+async fn process(
+    ctx: &LoginContext,
+    client: SocketAddr,
+    request: LoginRequest,
+) -> Result<LoginReply> {
+    match request {
+        LoginRequest::Attempt { name, password } => {
+            let reply = attempt_login(ctx, client, &name, &password).await?;
+
+            if ctx.config.log_user_ip {
+                let success = matches!(reply, LoginReply::Success(_));
+                if let Err(err) =
+                    ip_log::record(&ctx.pool, &name, client.ip(), success).await
+                {
+                    error!(logger: ctx.logger, "Could not log IP: {:?}", err);
+                }
+            }
+
+            Ok(reply)
+        }
+        LoginRequest::Create { name, password } => {
+            if !ctx.config.account_creation {
+                return Ok(LoginReply::CreateDisabled);
+            }
+
+            match validate_account(&name, &password, &ctx.config.banned_words) {
+                Ok(()) => create_account(ctx, &name, &password).await,
+                Err(err) => Ok(LoginReply::CreateInvalid(err)),
+            }
+        }
+        LoginRequest::ChangePassword {
+            name,
+            password,
+            new_password,
+        } => {
+            if new_password.is_empty() {
+                return Ok(LoginReply::ChangePasswordError);
+            }
+
+            change_password(ctx, &name, &password, &new_password).await
+        }
+    }
+}
+
+/// Looks up an account by its credentials, along with how its password
+/// matched so that legacy `PASSWORD()` hashes can be replaced once the
+/// login goes through.
+async fn find_account(
+    accounts: &dyn AccountStore,
+    name: &str,
+    password: &str,
+) -> Result<Option<(Account, Verification)>> {
+    let Some(account) = accounts.find_account(name).await? else {
+        return Ok(None);
+    };
+
+    match password::verify_async(password, &account.password).await? {
+        Verification::Invalid => Ok(None),
+        verification => Ok(Some((account, verification))),
     }
 }
 
-struct Session {
+async fn set_password(
+    accounts: &dyn AccountStore,
     acc_id: u32,
-    status: u32,
-}
-
-async fn attempt_login(conn: &Pool, name: &str, password: &str) -> Result<()> {
-    let session: Option<(u32, u32)> = r#"SELECT accounts.id,accounts.status 
-        FROM accounts 
-        WHERE accounts.login = :name 
-        AND accounts.password = PASSWORD(:password)"#
-        .with(params! {
-            name, password
-        })
-        .first(conn)
-        // .map(conn, |(acc_id, status)| Session { acc_id, status })
-        .await?;
-
-    if let Some((acc_id, status)) = session {
-        if status & ACCOUNT_STATUS_CODE_NORMAL > 0 {
-            post_login(acc_id, conn).await;
+    password: &str,
+) -> Result<()> {
+    let hash = password::hash_async(password).await?;
+    accounts.set_password(acc_id, &hash).await
+}
+
+async fn attempt_login(
+    ctx: &LoginContext,
+    client: SocketAddr,
+    name: &str,
+    password: &str,
+) -> Result<LoginReply> {
+    let accounts = &*ctx.accounts;
+
+    let Some((Account { acc_id, status, .. }, verification)) =
+        find_account(accounts, name, password).await?
+    else {
+        return Ok(LoginReply::Error);
+    };
+
+    if bans::is_banned(accounts, acc_id, status).await? {
+        return Ok(LoginReply::Banned);
+    }
+
+    // An expired ban has just been lifted, so only inactive accounts are
+    // left to refuse.
+    if status & (ACCOUNT_STATUS_CODE_NORMAL | ACCOUNT_STATUS_CODE_BANNED) == 0 {
+        return Ok(LoginReply::Error);
+    }
+
+    if verification == Verification::ValidNeedsRehash {
+        set_password(accounts, acc_id, password).await?;
+    }
+
+    if ctx.maint_mode() && !accounts.is_gm_account(acc_id).await? {
+        return Ok(LoginReply::Maintenance);
+    }
+
+    if exceeds_login_limit(ctx, client.ip(), acc_id).await? {
+        info!(
+            logger: ctx.logger,
+            "Refused login of account {}, too many sessions from {}",
+            acc_id,
+            client.ip()
+        );
+        return Ok(LoginReply::IpLimit);
+    }
+
+    let reply = post_login(ctx, acc_id).await?;
+
+    if reply == LoginReply::Success(acc_id) {
+        ctx.sessions
+            .insert(LoginSessionData::new(acc_id, name, client));
+    }
+
+    Ok(reply)
+}
+
+/// Checks `login.LOGIN_LIMIT` against the other accounts that are in game
+/// or logging in from `addr`.
+async fn exceeds_login_limit(
+    ctx: &LoginContext,
+    addr: IpAddr,
+    acc_id: u32,
+) -> Result<bool> {
+    if ctx.config.login_limit == 0 {
+        return Ok(false);
+    }
+
+    let addr = addr.to_canonical();
+    let mut accounts = ctx.sessions.accounts_from(addr);
+
+    // accounts_sessions only records IPv4 clients.
+    if let IpAddr::V4(client_addr) = addr {
+        accounts.extend(ctx.accounts.game_sessions_from(client_addr).await?);
+    }
+
+    accounts.sort_unstable();
+    accounts.dedup();
+    accounts.retain(|&other| other != acc_id);
+
+    Ok(accounts.len() >= ctx.config.login_limit as usize)
+}
+
+/// Deals with a character of the account that is still online, according to
+/// `login.EXISTING_SESSION`, then marks the account as logged in.
+async fn post_login(ctx: &LoginContext, acc_id: u32) -> Result<LoginReply> {
+    let existing = ctx.accounts.game_session(acc_id).await?;
+
+    if let Some(GameSession {
+        char_id, server, ..
+    }) = existing
+    {
+        match ctx.config.existing_session {
+            ExistingSession::Allow => {
+                info!(
+                    logger: ctx.logger,
+                    "Account {} logged in while character {} is online on {}",
+                    acc_id,
+                    char_id,
+                    server
+                );
+            }
+            ExistingSession::Refuse => {
+                info!(
+                    logger: ctx.logger,
+                    "Refused login of account {}, character {} is online on {}",
+                    acc_id,
+                    char_id,
+                    server
+                );
+                return Ok(LoginReply::AlreadyLoggedIn);
+            }
+            ExistingSession::Release => {
+                // Nothing tells the map server, so the character stays in
+                // game until it looks the session up again.
+                ctx.accounts.end_game_session(acc_id).await?;
+
+                info!(
+                    logger: ctx.logger,
+                    "Released session of character {} of account {}, \
+                    it stays online on {} until the map server drops it",
+                    char_id,
+                    acc_id,
+                    server
+                );
+            }
         }
     }
 
-    Ok(())
+    ctx.accounts.touch_account(acc_id).await?;
+
+    Ok(LoginReply::Success(acc_id))
 }
 
-async fn post_login(acc_id: u32, conn: &Pool) -> Result<()> {
-    r#"UPDATE accounts SET 
-        accounts.timelastmodify = NULL 
-        WHERE accounts.id = :acc_id"#
-        .with(params! {
-            acc_id
-        })
-        .ignore(conn)
-        .await?;
-
-    let x: Option<(u32, u64, u64)> = r#"SELECT charid, server_addr, server_port
-        FROM accounts_sessions JOIN accounts
-        ON accounts_sessions.accid = accounts.id
-        WHERE accounts.id = :acc_id"#
-        .with(params! {
-            acc_id
-        })
-        .first(conn)
-        .await?;
+async fn create_account(
+    ctx: &LoginContext,
+    name: &str,
+    password: &str,
+) -> Result<LoginReply> {
+    if ctx.accounts.find_account(name).await?.is_some() {
+        return Ok(LoginReply::CreateTaken);
+    }
 
-    Ok(())
+    let hash = password::hash_async(password).await?;
+
+    match ctx.accounts.create_account(name, &hash).await {
+        Ok(Some(acc_id)) => {
+            info!(logger: ctx.logger, "Created account {} ({})", name, acc_id);
+            Ok(LoginReply::CreateSuccess)
+        }
+        // Taken by another registration since the check above.
+        Ok(None) => Ok(LoginReply::CreateTaken),
+        Err(err) => {
+            error!(logger: ctx.logger, "Could not create account: {:?}", err);
+            Ok(LoginReply::CreateError)
+        }
+    }
+}
+
+async fn change_password(
+    ctx: &LoginContext,
+    name: &str,
+    password: &str,
+    new_password: &str,
+) -> Result<LoginReply> {
+    let accounts = &*ctx.accounts;
+
+    // The new password replaces any legacy hash, so there is no need to
+    // rehash the old one.
+    let Some((Account { acc_id, status, .. }, _)) =
+        find_account(accounts, name, password).await?
+    else {
+        return Ok(LoginReply::Error);
+    };
+
+    if bans::is_banned(accounts, acc_id, status).await?
+        || status & (ACCOUNT_STATUS_CODE_NORMAL | ACCOUNT_STATUS_CODE_BANNED)
+            == 0
+    {
+        return Ok(LoginReply::ChangePasswordError);
+    }
+
+    set_password(accounts, acc_id, new_password).await?;
+
+    Ok(LoginReply::ChangePasswordSuccess)
+}
+
+#[cfg(test)]
+impl LoginContext {
+    /// A context with the default settings over an in-memory store.
+    fn for_tests(store: Arc<store::MemoryStore>) -> LoginContext {
+        let lua = lua::Lua::new().unwrap();
+        let settings = Settings::new(&lua).unwrap();
+        let socket =
+            socket::socket_init_tcp(Logger::builder(), &settings).unwrap();
+
+        LoginContext {
+            // Never connects unless a query runs on it.
+            pool: Pool::new("mysql://localhost/xi"),
+            accounts: store.clone(),
+            characters: store,
+            config: LoginConfig::from_settings(&settings).unwrap(),
+            socket: Arc::new(socket),
+            sessions: LoginSessions::new(),
+            maint_mode: AtomicBool::new(false),
+            search_port: 54002,
+            logger: Logger::builder().build().unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use login_sessions::LobbyConnection;
+    use std::time::SystemTime;
+    use store::{test_character, test_client, MemoryStore};
+
+    /// Adds an account with a cheap hash, so that tests stay fast.
+    async fn add_account(store: &MemoryStore, name: &str) -> u32 {
+        let hash = bcrypt::hash("password", 4).unwrap();
+        store.create_account(name, &hash).await.unwrap().unwrap()
+    }
+
+    async fn login(
+        ctx: &LoginContext,
+        name: &str,
+        password: &str,
+    ) -> LoginReply {
+        let request = LoginRequest::Attempt {
+            name: name.to_owned(),
+            password: password.to_owned(),
+        };
+        process(ctx, test_client(), request).await.unwrap()
+    }
+
+    fn game_session(acc_id: u32, char_id: u32) -> GameSession {
+        GameSession {
+            acc_id,
+            char_id,
+            session_key: vec![0; 20],
+            server: "127.0.0.1:54230".parse().unwrap(),
+            client_addr: match test_client().ip() {
+                IpAddr::V4(addr) => u32::from(addr),
+                IpAddr::V6(_) => 0,
+            },
+            version_mismatch: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_creates_accounts_and_logs_in() {
+        let ctx = LoginContext::for_tests(Default::default());
+        let client = test_client();
+        let create = || LoginRequest::Create {
+            name: "shantotto".to_owned(),
+            password: "password".to_owned(),
+        };
+
+        let reply = process(&ctx, client, create()).await.unwrap();
+        assert_eq!(reply, LoginReply::CreateSuccess);
+        let reply = process(&ctx, client, create()).await.unwrap();
+        assert_eq!(reply, LoginReply::CreateTaken);
+
+        assert_eq!(
+            login(&ctx, "shantotto", "hunter2").await,
+            LoginReply::Error
+        );
+        assert_eq!(login(&ctx, "ajido", "password").await, LoginReply::Error);
+        assert!(ctx.sessions.accounts_from(client.ip()).is_empty());
+
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(ACCOUNT_ID_MIN)
+        );
+        assert_eq!(
+            ctx.sessions.accounts_from(client.ip()),
+            vec![ACCOUNT_ID_MIN]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_changes_passwords() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = LoginContext::for_tests(store.clone());
+        let acc_id = add_account(&store, "shantotto").await;
+        let change = |password: &str| LoginRequest::ChangePassword {
+            name: "shantotto".to_owned(),
+            password: password.to_owned(),
+            new_password: "hunter22".to_owned(),
+        };
+
+        let client = test_client();
+        let reply = process(&ctx, client, change("hunter2")).await.unwrap();
+        assert_eq!(reply, LoginReply::Error);
+
+        let reply = process(&ctx, client, change("password")).await.unwrap();
+        assert_eq!(reply, LoginReply::ChangePasswordSuccess);
+
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Error
+        );
+        assert_eq!(
+            login(&ctx, "shantotto", "hunter22").await,
+            LoginReply::Success(acc_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_refuses_banned_accounts() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = LoginContext::for_tests(store.clone());
+        let acc_id = add_account(&store, "shantotto").await;
+
+        store.ban_account(acc_id, "botting", None).await.unwrap();
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Banned
+        );
+
+        store.unban_account(acc_id).await.unwrap();
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+
+        // An expired ban is lifted on the next login.
+        let ended = SystemTime::now() - Duration::from_secs(60);
+        store
+            .ban_account(acc_id, "botting", Some(ended))
+            .await
+            .unwrap();
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+        assert_eq!(store.ban_active(acc_id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn it_rehashes_legacy_passwords_on_login() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = LoginContext::for_tests(store.clone());
+        // SELECT PASSWORD('password');
+        let legacy = "*2470C0C06DEE42FD1618BB99005ADCA2EC9D1E19";
+        let acc_id = store
+            .create_account("shantotto", legacy)
+            .await
+            .unwrap()
+            .unwrap();
+        let stored = || async {
+            store
+                .find_account("shantotto")
+                .await
+                .unwrap()
+                .unwrap()
+                .password
+        };
+
+        store.ban_account(acc_id, "botting", None).await.unwrap();
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Banned
+        );
+        assert_eq!(stored().await, legacy);
+
+        store.unban_account(acc_id).await.unwrap();
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+        assert!(stored().await.starts_with("$2b$"));
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_only_lets_gm_accounts_in_during_maintenance() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = LoginContext::for_tests(store.clone());
+        let acc_id = add_account(&store, "shantotto").await;
+        ctx.set_maint_mode(true);
+
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Maintenance
+        );
+
+        store.set_admin(acc_id);
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_ignores_deleted_gm_characters_during_maintenance() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = LoginContext::for_tests(store.clone());
+        let acc_id = add_account(&store, "shantotto").await;
+        let char_id = store
+            .create_character(
+                &ctx.config.new_character,
+                acc_id,
+                &test_character(),
+            )
+            .await
+            .unwrap();
+        store.set_gm_level(char_id, 1);
+        ctx.set_maint_mode(true);
+
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+
+        store
+            .delete_character(acc_id, char_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Maintenance
+        );
+    }
+
+    #[tokio::test]
+    async fn it_limits_logins_per_address() {
+        let store = Arc::new(MemoryStore::default());
+        let mut ctx = LoginContext::for_tests(store.clone());
+        ctx.config.login_limit = 1;
+        let acc_id = add_account(&store, "shantotto").await;
+        let other = add_account(&store, "ajido").await;
+
+        store
+            .start_game_session(&game_session(other, 1))
+            .await
+            .unwrap();
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::IpLimit
+        );
+
+        store.end_game_session(other).await.unwrap();
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_handles_existing_sessions() {
+        let store = Arc::new(MemoryStore::default());
+        let mut ctx = LoginContext::for_tests(store.clone());
+        let acc_id = add_account(&store, "shantotto").await;
+        store
+            .start_game_session(&game_session(acc_id, 1))
+            .await
+            .unwrap();
+
+        ctx.config.existing_session = ExistingSession::Refuse;
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::AlreadyLoggedIn
+        );
+        assert!(store.game_session(acc_id).await.unwrap().is_some());
+
+        ctx.config.existing_session = ExistingSession::Allow;
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+        assert_eq!(
+            store.game_session(acc_id).await.unwrap(),
+            Some(game_session(acc_id, 1))
+        );
+
+        // The lobby of the previous login, here with its data connection.
+        let (data, mut data_rx) = tokio::sync::mpsc::unbounded_channel();
+        assert!(ctx.sessions.attach(
+            acc_id,
+            test_client().ip(),
+            LobbyConnection::Data,
+            data
+        ));
+
+        ctx.config.existing_session = ExistingSession::Release;
+        assert_eq!(
+            login(&ctx, "shantotto", "password").await,
+            LoginReply::Success(acc_id)
+        );
+        assert!(store.game_session(acc_id).await.unwrap().is_none());
+        // The lobby of the previous login is closed, only the new one is
+        // left.
+        assert_eq!(data_rx.recv().await, None);
+        assert_eq!(
+            ctx.sessions.update(acc_id, test_client().ip(), |session| {
+                session.login_lobbydata_socket.is_some()
+            }),
+            Some(false)
+        );
+    }
 }
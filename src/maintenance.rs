@@ -0,0 +1,133 @@
+//! Database upkeep that the admin can schedule or run from the console.
+
+use anyhow::{bail, Result};
+use mysql_async::{prelude::*, Pool};
+use spdlog::{prelude::*, Logger};
+
+/// Tables that `OPTIMIZE TABLE` goes over, the ones that churn the most as
+/// players log in and out.
+const OPTIMIZED_TABLES: [&str; 15] = [
+    "accounts",
+    "accounts_banned",
+    "accounts_sessions",
+    "chars",
+    "char_equip",
+    "char_inventory",
+    "char_jobs",
+    "char_look",
+    "char_stats",
+    "char_vars",
+    "char_bazaar_msg",
+    "char_skills",
+    "char_titles",
+    "char_effects",
+    "char_exp",
+];
+
+/// A row of the `OPTIMIZE TABLE` result: table, operation, message type
+/// and message.
+type OptimizeRow = (String, String, String, String);
+
+/// Optimizes the tables one at a time and logs how each one went. A table
+/// that fails, e.g. because it does not exist, does not stop the others.
+/// Returns the number of tables optimized.
+pub async fn optimize_tables(pool: &Pool, logger: &Logger) -> usize {
+    info!(
+        logger: logger,
+        "Optimizing {} tables, each is locked while it runs",
+        OPTIMIZED_TABLES.len()
+    );
+
+    let mut optimized = 0;
+
+    for table in OPTIMIZED_TABLES {
+        match optimize_table(pool, table).await {
+            Ok(status) => {
+                optimized += 1;
+                info!(logger: logger, "Optimized table {}: {}", table, status);
+            }
+            Err(err) => {
+                error!(
+                    logger: logger,
+                    "Could not optimize table {}: {:#}", table, err
+                );
+            }
+        }
+    }
+
+    info!(
+        logger: logger,
+        "Optimized {} of {} tables",
+        optimized,
+        OPTIMIZED_TABLES.len()
+    );
+
+    optimized
+}
+
+async fn optimize_table(pool: &Pool, table: &str) -> Result<String> {
+    let rows: Vec<OptimizeRow> =
+        format!("OPTIMIZE TABLE `{}`", table).fetch(pool).await?;
+
+    status(&rows)
+}
+
+/// The final status of an `OPTIMIZE TABLE`. Failures such as a missing
+/// table come back as rows rather than as an error of the statement.
+fn status(rows: &[OptimizeRow]) -> Result<String> {
+    if let Some((_, _, _, message)) = rows
+        .iter()
+        .find(|(_, _, kind, _)| kind.eq_ignore_ascii_case("error"))
+    {
+        bail!("{}", message);
+    }
+
+    match rows
+        .iter()
+        .rev()
+        .find(|(_, _, kind, _)| kind.eq_ignore_ascii_case("status"))
+    {
+        Some((_, _, _, message)) => Ok(message.clone()),
+        None => bail!("no status reported"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(kind: &str, message: &str) -> OptimizeRow {
+        (
+            "xi.chars".to_owned(),
+            "optimize".to_owned(),
+            kind.to_owned(),
+            message.to_owned(),
+        )
+    }
+
+    #[test]
+    fn it_reads_optimize_status() {
+        let rows = [
+            row(
+                "note",
+                "Table does not support optimize, doing recreate + analyze \
+                instead",
+            ),
+            row("status", "OK"),
+        ];
+        assert_eq!(status(&rows).unwrap(), "OK");
+    }
+
+    #[test]
+    fn it_reports_optimize_errors() {
+        let rows = [
+            row("Error", "Table 'xi.chars' doesn't exist"),
+            row("status", "Operation failed"),
+        ];
+        assert_eq!(
+            status(&rows).unwrap_err().to_string(),
+            "Table 'xi.chars' doesn't exist"
+        );
+        assert!(status(&[]).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! The schema of the tables the login server uses, built up by numbered
+//! migrations that are compiled into the server.
+
+use anyhow::{bail, Context, Result};
+use mysql_async::{prelude::*, Pool};
+use spdlog::{prelude::*, Logger};
+
+/// A change to the schema, applied once and then recorded by its version.
+struct Migration {
+    version: u32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+const MIGRATIONS: [Migration; 3] = [
+    Migration {
+        version: 1,
+        name: "login_tables",
+        sql: include_str!("../migrations/0001_login_tables.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "deleted_characters",
+        sql: include_str!("../migrations/0002_deleted_characters.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "login_ip_log",
+        sql: include_str!("../migrations/0003_login_ip_log.sql"),
+    },
+];
+
+/// Refuses a database set up by a newer server, and warns about migrations
+/// it is missing, without changing it. Upgrades are left to the `migrate`
+/// command, so that an existing LandSandBoat database keeps working.
+pub async fn check(pool: &Pool, logger: &Logger) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let has_table: Option<u32> = r#"SELECT COUNT(*)
+        FROM information_schema.TABLES
+        WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'login_migrations'"#
+        .first(&mut conn)
+        .await?;
+
+    let applied: Vec<u32> = if has_table.unwrap_or(0) == 0 {
+        Vec::new()
+    } else {
+        "SELECT version FROM login_migrations"
+            .fetch(&mut conn)
+            .await?
+    };
+
+    for migration in pending(&applied)? {
+        warn!(
+            logger: logger,
+            "Database schema is missing migration {} ({}), run the server \
+            with `migrate` to apply it",
+            migration.version,
+            migration.name
+        );
+    }
+
+    Ok(())
+}
+
+/// Applies the migrations that the database is missing.
+///
+/// A database with migrations this server does not know was set up by a
+/// newer server, and is left alone. MySQL cannot roll back schema changes,
+/// so migrations are written to be applied again after a failure.
+pub async fn run(pool: &Pool, logger: &Logger) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    r#"CREATE TABLE IF NOT EXISTS `login_migrations` (
+        `version` int(10) unsigned NOT NULL,
+        `name` varchar(64) NOT NULL,
+        `applied` datetime NOT NULL,
+        PRIMARY KEY (`version`)
+    )"#
+    .ignore(&mut conn)
+    .await?;
+
+    let applied: Vec<u32> = "SELECT version FROM login_migrations"
+        .fetch(&mut conn)
+        .await?;
+
+    for migration in pending(&applied)? {
+        info!(
+            logger: logger,
+            "Applying migration {} ({})", migration.version, migration.name
+        );
+
+        for statement in statements(migration.sql) {
+            statement.ignore(&mut conn).await.with_context(|| {
+                format!("Migration {} failed", migration.version)
+            })?;
+        }
+
+        r#"INSERT INTO login_migrations(version, name, applied)
+            VALUES(:version, :name, NOW())"#
+            .with(params! {
+                "version" => migration.version,
+                "name" => migration.name,
+            })
+            .ignore(&mut conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The migrations missing from `applied`, in order.
+fn pending(applied: &[u32]) -> Result<Vec<&'static Migration>> {
+    let latest = MIGRATIONS.last().map_or(0, |migration| migration.version);
+
+    if let Some(&newest) = applied.iter().max() {
+        if newest > latest {
+            bail!(
+                "Database schema version {} is newer than this server, \
+                which knows up to version {}.",
+                newest,
+                latest
+            );
+        }
+    }
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect())
+}
+
+/// Splits a migration into its statements, without comments.
+fn statements(sql: &str) -> impl Iterator<Item = String> + '_ {
+    sql.split(';')
+        .map(|statement| {
+            statement
+                .lines()
+                .filter(|line| !line.trim_start().starts_with("--"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .filter(|statement| !statement.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_numbers_migrations_in_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+            assert!(statements(migration.sql).next().is_some());
+        }
+    }
+
+    #[test]
+    fn it_splits_statements() {
+        let statements: Vec<String> = statements(MIGRATIONS[1].sql).collect();
+        assert_eq!(statements.len(), 4);
+        assert!(statements[0].trim().starts_with("SET @add_deleted"));
+        assert!(statements[0].contains("information_schema.COLUMNS"));
+        assert_eq!(statements[2].trim(), "EXECUTE add_deleted");
+
+        assert_eq!(
+            super::statements("-- comment\nSELECT 1;\n\nSELECT 2;\n")
+                .map(|statement| statement.trim().to_owned())
+                .collect::<Vec<_>>(),
+            vec!["SELECT 1", "SELECT 2"]
+        );
+    }
+
+    #[test]
+    fn it_finds_pending_migrations() {
+        let latest = MIGRATIONS.len() as u32;
+        let versions = |applied: &[u32]| {
+            pending(applied)
+                .unwrap()
+                .iter()
+                .map(|migration| migration.version)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(versions(&[]), (1..=latest).collect::<Vec<_>>());
+        let missing = versions(&[1, 3]);
+        assert_eq!(missing[0], 2);
+        assert!(!missing.contains(&1) && !missing.contains(&3));
+        assert!(versions(&(1..=latest).collect::<Vec<_>>()).is_empty());
+    }
+
+    #[test]
+    fn it_refuses_newer_databases() {
+        let latest = MIGRATIONS.len() as u32;
+        assert!(pending(&[1, latest + 1]).is_err());
+    }
+}
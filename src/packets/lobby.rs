@@ -0,0 +1,302 @@
+//! Framing of the packets exchanged on the lobby view and conf connections.
+//!
+//! Every packet starts with its length, the "IXFF" magic, its command and an
+//! MD5 hash of the whole packet, taken while the hash field is zeroed.
+
+use std::io;
+use std::ops::{Deref, DerefMut};
+
+use bytes::{Buf, BytesMut};
+use md5::{Digest, Md5};
+use thiserror::Error;
+use tokio_util::codec::{Decoder, Encoder};
+
+const LOBBY_MAGIC: &[u8; 4] = b"IXFF";
+const MAGIC_OFFSET: usize = 4;
+const CMD_OFFSET: usize = 8;
+const HASH_OFFSET: usize = 12;
+const HASH_LEN: usize = 16;
+
+/// Length of the header, up to the end of the hash.
+const HEADER_LEN: usize = HASH_OFFSET + HASH_LEN;
+const MAX_PACKET_LEN: usize = 0x1000;
+
+/// Commands of the lobby view and conf connections, sent by the client or
+/// the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LobbyCommand {
+    Ok = 0x03,
+    Error = 0x04,
+    VersionReply = 0x05,
+    Select = 0x07,
+    Reservation = 0x0B,
+    Delete = 0x14,
+    CharList = 0x1F,
+    CharListReply = 0x20,
+    Create = 0x21,
+    ReserveName = 0x22,
+    WorldListReply = 0x23,
+    WorldList = 0x24,
+    Version = 0x26,
+    ConfigRequest = 0x2A,
+    ConfigReply = 0x2B,
+    AccountRequest = 0x2C,
+    AccountReply = 0x2D,
+}
+
+impl LobbyCommand {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0x03 => Some(LobbyCommand::Ok),
+            0x04 => Some(LobbyCommand::Error),
+            0x05 => Some(LobbyCommand::VersionReply),
+            0x07 => Some(LobbyCommand::Select),
+            0x0B => Some(LobbyCommand::Reservation),
+            0x14 => Some(LobbyCommand::Delete),
+            0x1F => Some(LobbyCommand::CharList),
+            0x20 => Some(LobbyCommand::CharListReply),
+            0x21 => Some(LobbyCommand::Create),
+            0x22 => Some(LobbyCommand::ReserveName),
+            0x23 => Some(LobbyCommand::WorldListReply),
+            0x24 => Some(LobbyCommand::WorldList),
+            0x26 => Some(LobbyCommand::Version),
+            0x2A => Some(LobbyCommand::ConfigRequest),
+            0x2B => Some(LobbyCommand::ConfigReply),
+            0x2C => Some(LobbyCommand::AccountRequest),
+            0x2D => Some(LobbyCommand::AccountReply),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FrameError {
+    #[error("invalid lobby packet length {0}")]
+    Length(usize),
+    #[error("lobby packet without the IXFF magic")]
+    Magic,
+    #[error("lobby packet hash does not match its contents")]
+    Hash,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A whole lobby packet, header included, so that fields are found at the
+/// offsets the client uses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LobbyPacket {
+    data: Vec<u8>,
+}
+
+impl LobbyPacket {
+    /// Starts a packet of `len` bytes with its header filled in. `len` must
+    /// leave room for the header.
+    pub fn new(command: LobbyCommand, len: usize) -> Self {
+        assert!((HEADER_LEN..=MAX_PACKET_LEN).contains(&len));
+
+        let mut data = vec![0; len];
+        data[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+        data[MAGIC_OFFSET..MAGIC_OFFSET + 4].copy_from_slice(LOBBY_MAGIC);
+        data[CMD_OFFSET] = command as u8;
+        Self { data }
+    }
+
+    /// The command, or `None` if it is not one the server knows.
+    pub fn command(&self) -> Option<LobbyCommand> {
+        LobbyCommand::from_id(self.command_id())
+    }
+
+    pub fn command_id(&self) -> u8 {
+        self.data[CMD_OFFSET]
+    }
+
+    /// Writes the hash of the packet into its header, once its contents
+    /// are final, and returns it ready to send.
+    pub fn seal(mut self) -> Vec<u8> {
+        let hash = hash(&mut self.data);
+        self.data[HASH_OFFSET..HASH_OFFSET + HASH_LEN].copy_from_slice(&hash);
+        self.data
+    }
+}
+
+impl Deref for LobbyPacket {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for LobbyPacket {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+/// Hashes a packet with its hash field zeroed.
+fn hash(data: &mut [u8]) -> [u8; HASH_LEN] {
+    data[HASH_OFFSET..HASH_OFFSET + HASH_LEN].fill(0);
+    Md5::digest(&*data).into()
+}
+
+/// Splits lobby packets out of a stream, and seals the ones sent back.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LobbyCodec;
+
+impl Decoder for LobbyCodec {
+    type Item = LobbyPacket;
+    type Error = FrameError;
+
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<LobbyPacket>, FrameError> {
+        let Some(len) = src.get(0..4) else {
+            return Ok(None);
+        };
+
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        if !(HEADER_LEN..=MAX_PACKET_LEN).contains(&len) {
+            return Err(FrameError::Length(len));
+        }
+
+        if src.len() < len {
+            src.reserve(len - src.len());
+            return Ok(None);
+        }
+
+        let mut data = src[..len].to_vec();
+        src.advance(len);
+
+        if &data[MAGIC_OFFSET..MAGIC_OFFSET + 4] != LOBBY_MAGIC {
+            return Err(FrameError::Magic);
+        }
+
+        let sent: [u8; HASH_LEN] = data[HASH_OFFSET..HASH_OFFSET + HASH_LEN]
+            .try_into()
+            .unwrap();
+        if hash(&mut data) != sent {
+            return Err(FrameError::Hash);
+        }
+        data[HASH_OFFSET..HASH_OFFSET + HASH_LEN].copy_from_slice(&sent);
+
+        Ok(Some(LobbyPacket { data }))
+    }
+}
+
+impl Encoder<LobbyPacket> for LobbyCodec {
+    type Error = FrameError;
+
+    fn encode(
+        &mut self,
+        packet: LobbyPacket,
+        dst: &mut BytesMut,
+    ) -> Result<(), FrameError> {
+        dst.extend_from_slice(&packet.seal());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn encode(packet: LobbyPacket) -> BytesMut {
+        let mut bytes = BytesMut::new();
+        LobbyCodec.encode(packet, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn it_seals_packets() {
+        let mut packet = LobbyPacket::new(LobbyCommand::Reservation, 0x48);
+        assert_eq!(&packet[0..4], &0x48u32.to_le_bytes());
+        assert_eq!(&packet[4..8], b"IXFF");
+        assert_eq!(packet.command(), Some(LobbyCommand::Reservation));
+
+        let sealed = packet.clone().seal();
+        let hash = sealed[12..28].to_vec();
+        assert_ne!(hash, vec![0; 16]);
+        assert_eq!(&Md5::digest(&packet[..])[..], &hash[..]);
+
+        packet[40] = 1;
+        assert_ne!(&packet.seal()[12..28], &hash[..]);
+    }
+
+    #[test]
+    fn it_decodes_split_packets() {
+        let mut packet = LobbyPacket::new(LobbyCommand::WorldList, 0x30);
+        packet[40] = 7;
+        let bytes = encode(packet.clone());
+
+        let mut src = BytesMut::from(&bytes[..10]);
+        assert!(LobbyCodec.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&bytes[10..]);
+        src.extend_from_slice(&bytes[..4]);
+        let decoded = LobbyCodec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(decoded.command(), Some(LobbyCommand::WorldList));
+        assert_eq!(&decoded[..], &bytes[..]);
+
+        // The start of the next packet is left for later.
+        assert_eq!(src.len(), 4);
+        assert!(LobbyCodec.decode(&mut src).unwrap().is_none());
+    }
+
+    #[test]
+    fn it_refuses_unsealed_packets() {
+        let packet = LobbyPacket::new(LobbyCommand::Version, 0x98);
+        let mut src = BytesMut::from(&packet[..]);
+        assert!(matches!(LobbyCodec.decode(&mut src), Err(FrameError::Hash)));
+    }
+
+    #[test]
+    fn it_refuses_invalid_packets() {
+        let mut src = BytesMut::from(&0x10u32.to_le_bytes()[..]);
+        assert!(matches!(
+            LobbyCodec.decode(&mut src),
+            Err(FrameError::Length(0x10))
+        ));
+
+        let mut bytes = encode(LobbyPacket::new(LobbyCommand::Ok, 0x20));
+        bytes[4] = b'X';
+        assert!(matches!(
+            LobbyCodec.decode(&mut bytes),
+            Err(FrameError::Magic)
+        ));
+
+        let mut bytes = encode(LobbyPacket::new(LobbyCommand::Ok, 0x20));
+        bytes[30] = 1;
+        assert!(matches!(
+            LobbyCodec.decode(&mut bytes),
+            Err(FrameError::Hash)
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn it_round_trips_packets(
+            command in 0u8..=0xff,
+            body in vec(any::<u8>(), 0..0x200),
+        ) {
+            let mut packet =
+                LobbyPacket::new(LobbyCommand::Ok, HEADER_LEN + body.len());
+            packet[CMD_OFFSET] = command;
+            packet[HEADER_LEN..].copy_from_slice(&body);
+
+            let mut bytes = encode(packet.clone());
+            let decoded = LobbyCodec.decode(&mut bytes).unwrap().unwrap();
+            prop_assert_eq!(decoded.command_id(), command);
+            prop_assert_eq!(&decoded[HEADER_LEN..], &body[..]);
+            prop_assert!(bytes.is_empty());
+        }
+
+        #[test]
+        fn it_decodes_any_bytes(bytes in vec(any::<u8>(), 0..0x100)) {
+            let mut src = BytesMut::from(&bytes[..]);
+            while let Ok(Some(_)) = LobbyCodec.decode(&mut src) {}
+        }
+    }
+}
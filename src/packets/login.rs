@@ -0,0 +1,473 @@
+//! Requests the loader sends on the auth port, and the replies to them.
+//!
+//! A request holds the account name and password in two NUL padded fields,
+//! followed by its opcode. Changing the password appends the new one.
+//!
+//! Encoding requests and decoding replies is the loader's side of the
+//! protocol, which the server only uses to test and fuzz its own.
+
+use std::ops::Range;
+
+use thiserror::Error;
+
+use super::read_field;
+use crate::validation::AccountError;
+
+/// Longest string that fits in a field, without its terminator.
+#[cfg(any(test, fuzzing))]
+const FIELD_LEN: usize = 16;
+const NAME: Range<usize> = 0..16;
+const PASSWORD: Range<usize> = 16..32;
+const OPCODE_OFFSET: usize = 32;
+const NEW_PASSWORD: Range<usize> = 33..49;
+
+/// Length of every request up to its opcode.
+pub const REQUEST_LEN: usize = 33;
+const CHANGE_PASSWORD_REQUEST_LEN: usize = 49;
+
+const SUCCESS_REPLY_LEN: usize = 33;
+
+const LOGIN_ATTEMPT: u8 = 0x10;
+const LOGIN_CREATE: u8 = 0x20;
+const LOGIN_CHANGE_PASSWORD: u8 = 0x30;
+
+const LOGIN_SUCCESS: u8 = 0x01;
+const LOGIN_ERROR: u8 = 0x02;
+const LOGIN_SUCCESS_CREATE: u8 = 0x03;
+const LOGIN_ERROR_CREATE: u8 = 0x04;
+const LOGIN_ERROR_ALREADY_LOGGED_IN: u8 = 0x05;
+const LOGIN_SUCCESS_CHANGE_PASSWORD: u8 = 0x06;
+const LOGIN_ERROR_CHANGE_PASSWORD: u8 = 0x07;
+const LOGIN_ERROR_CREATE_DISABLED: u8 = 0x08;
+const LOGIN_ERROR_CREATE_TAKEN: u8 = 0x09;
+const LOGIN_ERROR_BANNED: u8 = 0x0A;
+const LOGIN_ERROR_CREATE_NAME_LENGTH: u8 = 0x0B;
+const LOGIN_ERROR_CREATE_NAME_CHARSET: u8 = 0x0C;
+const LOGIN_ERROR_CREATE_NAME_BANNED: u8 = 0x0D;
+const LOGIN_ERROR_CREATE_PASSWORD_LENGTH: u8 = 0x0E;
+const LOGIN_ERROR_CREATE_PASSWORD_CHARSET: u8 = 0x0F;
+const LOGIN_ERROR_IP_LIMIT: u8 = 0x11;
+const LOGIN_ERROR_MAINTENANCE: u8 = 0x12;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PacketError {
+    #[error("expected a {expected} byte packet, got {actual} bytes")]
+    Length { expected: usize, actual: usize },
+    #[error("unknown opcode {0:#04x}")]
+    UnknownOpcode(u8),
+    #[cfg(any(test, fuzzing))]
+    #[error("unknown reply code {0:#04x}")]
+    UnknownReply(u8),
+    #[error("{0} is not a valid string")]
+    InvalidField(&'static str),
+    #[cfg(any(test, fuzzing))]
+    #[error("{0} does not fit in its field")]
+    FieldTooLong(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoginRequest {
+    Attempt {
+        name: String,
+        password: String,
+    },
+    Create {
+        name: String,
+        password: String,
+    },
+    ChangePassword {
+        name: String,
+        password: String,
+        new_password: String,
+    },
+}
+
+/// Length of the whole request, going by the opcode at the end of its first
+/// `REQUEST_LEN` bytes.
+pub fn request_len(head: &[u8]) -> usize {
+    match head.get(OPCODE_OFFSET) {
+        Some(&LOGIN_CHANGE_PASSWORD) => CHANGE_PASSWORD_REQUEST_LEN,
+        _ => REQUEST_LEN,
+    }
+}
+
+impl LoginRequest {
+    pub fn decode(bytes: &[u8]) -> Result<Self, PacketError> {
+        let expected = request_len(bytes);
+        if bytes.len() != expected {
+            return Err(PacketError::Length {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let opcode = bytes[OPCODE_OFFSET];
+
+        let name = decode_field(&bytes[NAME], "name")?;
+        let password = decode_field(&bytes[PASSWORD], "password")?;
+
+        match opcode {
+            LOGIN_ATTEMPT => Ok(LoginRequest::Attempt { name, password }),
+            LOGIN_CREATE => Ok(LoginRequest::Create { name, password }),
+            LOGIN_CHANGE_PASSWORD => Ok(LoginRequest::ChangePassword {
+                name,
+                password,
+                new_password: decode_field(
+                    &bytes[NEW_PASSWORD],
+                    "new password",
+                )?,
+            }),
+            _ => Err(PacketError::UnknownOpcode(opcode)),
+        }
+    }
+
+    #[cfg(any(test, fuzzing))]
+    pub fn encode(&self) -> Result<Vec<u8>, PacketError> {
+        let (opcode, len, name, password) = match self {
+            LoginRequest::Attempt { name, password } => {
+                (LOGIN_ATTEMPT, REQUEST_LEN, name, password)
+            }
+            LoginRequest::Create { name, password } => {
+                (LOGIN_CREATE, REQUEST_LEN, name, password)
+            }
+            LoginRequest::ChangePassword { name, password, .. } => (
+                LOGIN_CHANGE_PASSWORD,
+                CHANGE_PASSWORD_REQUEST_LEN,
+                name,
+                password,
+            ),
+        };
+
+        let mut bytes = vec![0; len];
+        encode_field(&mut bytes[NAME], name, "name")?;
+        encode_field(&mut bytes[PASSWORD], password, "password")?;
+        bytes[OPCODE_OFFSET] = opcode;
+
+        if let LoginRequest::ChangePassword { new_password, .. } = self {
+            encode_field(
+                &mut bytes[NEW_PASSWORD],
+                new_password,
+                "new password",
+            )?;
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn decode_field(
+    bytes: &[u8],
+    field: &'static str,
+) -> Result<String, PacketError> {
+    read_field(bytes)
+        .map(str::to_owned)
+        .ok_or(PacketError::InvalidField(field))
+}
+
+/// Copies `s` into a NUL padded field. Unlike lobby strings, credentials
+/// are refused rather than truncated when they do not fit.
+#[cfg(any(test, fuzzing))]
+fn encode_field(
+    bytes: &mut [u8],
+    s: &str,
+    field: &'static str,
+) -> Result<(), PacketError> {
+    if s.len() > FIELD_LEN {
+        return Err(PacketError::FieldTooLong(field));
+    }
+    if s.contains('\0') {
+        return Err(PacketError::InvalidField(field));
+    }
+
+    bytes[..s.len()].copy_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// Replies sent back to the loader on the auth port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoginReply {
+    Success(u32),
+    Error,
+    Banned,
+    AlreadyLoggedIn,
+    IpLimit,
+    Maintenance,
+    CreateSuccess,
+    CreateError,
+    CreateDisabled,
+    CreateTaken,
+    CreateInvalid(AccountError),
+    ChangePasswordSuccess,
+    ChangePasswordError,
+}
+
+impl LoginReply {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            LoginReply::Success(acc_id) => {
+                let mut bytes = vec![0; SUCCESS_REPLY_LEN];
+                bytes[0] = LOGIN_SUCCESS;
+                bytes[1..5].copy_from_slice(&acc_id.to_le_bytes());
+                bytes
+            }
+            LoginReply::Error => vec![LOGIN_ERROR],
+            LoginReply::Banned => vec![LOGIN_ERROR_BANNED],
+            LoginReply::AlreadyLoggedIn => vec![LOGIN_ERROR_ALREADY_LOGGED_IN],
+            LoginReply::IpLimit => vec![LOGIN_ERROR_IP_LIMIT],
+            LoginReply::Maintenance => vec![LOGIN_ERROR_MAINTENANCE],
+            LoginReply::CreateSuccess => vec![LOGIN_SUCCESS_CREATE],
+            LoginReply::CreateError => vec![LOGIN_ERROR_CREATE],
+            LoginReply::CreateDisabled => vec![LOGIN_ERROR_CREATE_DISABLED],
+            LoginReply::CreateTaken => vec![LOGIN_ERROR_CREATE_TAKEN],
+            LoginReply::CreateInvalid(err) => vec![match err {
+                AccountError::NameLength => LOGIN_ERROR_CREATE_NAME_LENGTH,
+                AccountError::NameCharset => LOGIN_ERROR_CREATE_NAME_CHARSET,
+                AccountError::NameBanned => LOGIN_ERROR_CREATE_NAME_BANNED,
+                AccountError::PasswordLength => {
+                    LOGIN_ERROR_CREATE_PASSWORD_LENGTH
+                }
+                AccountError::PasswordCharset => {
+                    LOGIN_ERROR_CREATE_PASSWORD_CHARSET
+                }
+            }],
+            LoginReply::ChangePasswordSuccess => {
+                vec![LOGIN_SUCCESS_CHANGE_PASSWORD]
+            }
+            LoginReply::ChangePasswordError => {
+                vec![LOGIN_ERROR_CHANGE_PASSWORD]
+            }
+        }
+    }
+
+    /// Reads a reply the way the loader does, mostly useful to check
+    /// `encode`.
+    #[cfg(any(test, fuzzing))]
+    pub fn decode(bytes: &[u8]) -> Result<Self, PacketError> {
+        let Some(&code) = bytes.first() else {
+            return Err(PacketError::Length {
+                expected: 1,
+                actual: 0,
+            });
+        };
+
+        let expected = match code {
+            LOGIN_SUCCESS => SUCCESS_REPLY_LEN,
+            _ => 1,
+        };
+        if bytes.len() != expected {
+            return Err(PacketError::Length {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        Ok(match code {
+            LOGIN_SUCCESS => LoginReply::Success(u32::from_le_bytes([
+                bytes[1], bytes[2], bytes[3], bytes[4],
+            ])),
+            LOGIN_ERROR => LoginReply::Error,
+            LOGIN_ERROR_BANNED => LoginReply::Banned,
+            LOGIN_ERROR_ALREADY_LOGGED_IN => LoginReply::AlreadyLoggedIn,
+            LOGIN_ERROR_IP_LIMIT => LoginReply::IpLimit,
+            LOGIN_ERROR_MAINTENANCE => LoginReply::Maintenance,
+            LOGIN_SUCCESS_CREATE => LoginReply::CreateSuccess,
+            LOGIN_ERROR_CREATE => LoginReply::CreateError,
+            LOGIN_ERROR_CREATE_DISABLED => LoginReply::CreateDisabled,
+            LOGIN_ERROR_CREATE_TAKEN => LoginReply::CreateTaken,
+            LOGIN_ERROR_CREATE_NAME_LENGTH => {
+                LoginReply::CreateInvalid(AccountError::NameLength)
+            }
+            LOGIN_ERROR_CREATE_NAME_CHARSET => {
+                LoginReply::CreateInvalid(AccountError::NameCharset)
+            }
+            LOGIN_ERROR_CREATE_NAME_BANNED => {
+                LoginReply::CreateInvalid(AccountError::NameBanned)
+            }
+            LOGIN_ERROR_CREATE_PASSWORD_LENGTH => {
+                LoginReply::CreateInvalid(AccountError::PasswordLength)
+            }
+            LOGIN_ERROR_CREATE_PASSWORD_CHARSET => {
+                LoginReply::CreateInvalid(AccountError::PasswordCharset)
+            }
+            LOGIN_SUCCESS_CHANGE_PASSWORD => LoginReply::ChangePasswordSuccess,
+            LOGIN_ERROR_CHANGE_PASSWORD => LoginReply::ChangePasswordError,
+            _ => return Err(PacketError::UnknownReply(code)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn request(opcode: u8, name: &[u8], password: &[u8]) -> Vec<u8> {
+        let len = match opcode {
+            LOGIN_CHANGE_PASSWORD => CHANGE_PASSWORD_REQUEST_LEN,
+            _ => REQUEST_LEN,
+        };
+        let mut bytes = vec![0; len];
+        bytes[..name.len()].copy_from_slice(name);
+        bytes[16..16 + password.len()].copy_from_slice(password);
+        bytes[OPCODE_OFFSET] = opcode;
+        bytes
+    }
+
+    #[test]
+    fn it_decodes_requests() {
+        let bytes = request(LOGIN_ATTEMPT, b"admin", b"hunter2");
+        assert_eq!(
+            LoginRequest::decode(&bytes),
+            Ok(LoginRequest::Attempt {
+                name: "admin".to_string(),
+                password: "hunter2".to_string(),
+            })
+        );
+
+        let mut bytes = request(LOGIN_CHANGE_PASSWORD, b"admin", b"hunter2");
+        bytes[33..41].copy_from_slice(b"hunter42");
+        assert_eq!(
+            LoginRequest::decode(&bytes),
+            Ok(LoginRequest::ChangePassword {
+                name: "admin".to_string(),
+                password: "hunter2".to_string(),
+                new_password: "hunter42".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn it_refuses_malformed_requests() {
+        assert_eq!(
+            LoginRequest::decode(&[0; 10]),
+            Err(PacketError::Length {
+                expected: 33,
+                actual: 10
+            })
+        );
+
+        // The new password of a change password request is missing.
+        let bytes = request(LOGIN_CHANGE_PASSWORD, b"admin", b"hunter2");
+        assert_eq!(
+            LoginRequest::decode(&bytes[..33]),
+            Err(PacketError::Length {
+                expected: 49,
+                actual: 33
+            })
+        );
+
+        let bytes = request(0x40, b"admin", b"hunter2");
+        assert_eq!(
+            LoginRequest::decode(&bytes),
+            Err(PacketError::UnknownOpcode(0x40))
+        );
+
+        let bytes = request(LOGIN_ATTEMPT, &[0xff; 4], b"hunter2");
+        assert_eq!(
+            LoginRequest::decode(&bytes),
+            Err(PacketError::InvalidField("name"))
+        );
+    }
+
+    #[test]
+    fn it_refuses_to_encode_long_fields() {
+        let request = LoginRequest::Create {
+            name: "a".repeat(17),
+            password: "hunter2".to_string(),
+        };
+        assert_eq!(request.encode(), Err(PacketError::FieldTooLong("name")));
+    }
+
+    #[test]
+    fn it_encodes_login_reply() {
+        let bytes = LoginReply::Success(1000).encode();
+        assert_eq!(bytes.len(), 33);
+        assert_eq!(bytes[0], LOGIN_SUCCESS);
+        assert_eq!(&bytes[1..5], &1000u32.to_le_bytes());
+
+        assert_eq!(LoginReply::Banned.encode(), vec![LOGIN_ERROR_BANNED]);
+        assert_eq!(
+            LoginReply::CreateDisabled.encode(),
+            vec![LOGIN_ERROR_CREATE_DISABLED]
+        );
+        assert_eq!(
+            LoginReply::CreateInvalid(AccountError::NameBanned).encode(),
+            vec![LOGIN_ERROR_CREATE_NAME_BANNED]
+        );
+    }
+
+    /// Strings that fit in a field: any ASCII, or a few characters of any
+    /// width.
+    fn field() -> impl Strategy<Value = String> {
+        prop_oneof!["[\\x01-\\x7f]{0,16}", "[^\\x00]{0,4}"]
+    }
+
+    fn login_request() -> impl Strategy<Value = LoginRequest> {
+        prop_oneof![
+            (field(), field()).prop_map(|(name, password)| {
+                LoginRequest::Attempt { name, password }
+            }),
+            (field(), field()).prop_map(|(name, password)| {
+                LoginRequest::Create { name, password }
+            }),
+            (field(), field(), field()).prop_map(
+                |(name, password, new_password)| {
+                    LoginRequest::ChangePassword {
+                        name,
+                        password,
+                        new_password,
+                    }
+                }
+            ),
+        ]
+    }
+
+    fn login_reply() -> impl Strategy<Value = LoginReply> {
+        prop_oneof![
+            any::<u32>().prop_map(LoginReply::Success),
+            Just(LoginReply::Error),
+            Just(LoginReply::Banned),
+            Just(LoginReply::AlreadyLoggedIn),
+            Just(LoginReply::IpLimit),
+            Just(LoginReply::Maintenance),
+            Just(LoginReply::CreateSuccess),
+            Just(LoginReply::CreateError),
+            Just(LoginReply::CreateDisabled),
+            Just(LoginReply::CreateTaken),
+            Just(LoginReply::CreateInvalid(AccountError::NameLength)),
+            Just(LoginReply::CreateInvalid(AccountError::NameCharset)),
+            Just(LoginReply::CreateInvalid(AccountError::NameBanned)),
+            Just(LoginReply::CreateInvalid(AccountError::PasswordLength)),
+            Just(LoginReply::CreateInvalid(AccountError::PasswordCharset)),
+            Just(LoginReply::ChangePasswordSuccess),
+            Just(LoginReply::ChangePasswordError),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn it_round_trips_requests(request in login_request()) {
+            let bytes = request.encode().unwrap();
+            prop_assert_eq!(LoginRequest::decode(&bytes), Ok(request));
+        }
+
+        #[test]
+        fn it_round_trips_replies(reply in login_reply()) {
+            prop_assert_eq!(LoginReply::decode(&reply.encode()), Ok(reply));
+        }
+
+        #[test]
+        fn it_decodes_any_bytes(bytes in vec(any::<u8>(), 0..64)) {
+            // Whatever decodes must encode back to the same request.
+            if let Ok(request) = LoginRequest::decode(&bytes) {
+                let encoded = request.encode().unwrap();
+                prop_assert_eq!(LoginRequest::decode(&encoded), Ok(request));
+            }
+            if let Ok(reply) = LoginReply::decode(&bytes) {
+                let encoded = reply.encode();
+                prop_assert_eq!(LoginReply::decode(&encoded), Ok(reply));
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Wire formats of the packets exchanged with the loader and the client.
+
+pub mod lobby;
+pub mod login;
+
+/// Reads a NUL terminated string field. Whatever follows the terminator is
+/// padding and ignored.
+pub fn read_field(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..len]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_trims_field_padding() {
+        let mut field = [0; 16];
+        field[0..5].copy_from_slice(b"admin");
+        assert_eq!(read_field(&field), Some("admin"));
+
+        field[8] = 0xff;
+        assert_eq!(read_field(&field), Some("admin"));
+
+        assert_eq!(read_field(b"admin"), Some("admin"));
+        assert_eq!(read_field(&[0xff; 16]), None);
+    }
+}
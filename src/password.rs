@@ -0,0 +1,128 @@
+use anyhow::Result;
+use sha1::{Digest, Sha1};
+
+/// Cost factor for new bcrypt hashes.
+const BCRYPT_COST: u32 = 12;
+
+/// Result of checking a password against a stored hash.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// The password matched a legacy hash that should be replaced by a fresh
+    /// one from `hash`.
+    ValidNeedsRehash,
+}
+
+/// Hashes a password for storage in `accounts.password`.
+///
+/// The result is a modular crypt string (`$2b$<cost>$...`), which records the
+/// algorithm and cost alongside the hash so that they can change later.
+pub fn hash(password: &str) -> Result<String> {
+    Ok(bcrypt::hash(password, BCRYPT_COST)?)
+}
+
+/// Checks `password` against a hash produced by `hash` or by MySQL's
+/// deprecated `PASSWORD()` function.
+pub fn verify(password: &str, stored: &str) -> Result<Verification> {
+    if stored.starts_with('$') {
+        return Ok(if bcrypt::verify(password, stored)? {
+            Verification::Valid
+        } else {
+            Verification::Invalid
+        });
+    }
+
+    if stored.starts_with('*') {
+        return Ok(if constant_time_eq(&mysql_password(password), stored) {
+            Verification::ValidNeedsRehash
+        } else {
+            Verification::Invalid
+        });
+    }
+
+    Ok(Verification::Invalid)
+}
+
+/// Runs `hash` on the blocking thread pool, since bcrypt is deliberately
+/// slow.
+pub async fn hash_async(password: &str) -> Result<String> {
+    let password = password.to_owned();
+    tokio::task::spawn_blocking(move || hash(&password)).await?
+}
+
+/// Runs `verify` on the blocking thread pool.
+pub async fn verify_async(
+    password: &str,
+    stored: &str,
+) -> Result<Verification> {
+    let password = password.to_owned();
+    let stored = stored.to_owned();
+    tokio::task::spawn_blocking(move || verify(&password, &stored)).await?
+}
+
+/// Equivalent of MySQL's `PASSWORD()`: `*` followed by the upper case hex
+/// digest of SHA1(SHA1(password)).
+fn mysql_password(password: &str) -> String {
+    let digest = Sha1::digest(Sha1::digest(password.as_bytes()));
+
+    let mut result = String::with_capacity(41);
+    result.push('*');
+    for byte in digest {
+        result.push_str(&format!("{:02X}", byte));
+    }
+
+    result
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_matches_mysql_password() {
+        // SELECT PASSWORD('password');
+        assert_eq!(
+            mysql_password("password"),
+            "*2470C0C06DEE42FD1618BB99005ADCA2EC9D1E19"
+        );
+    }
+
+    #[test]
+    fn it_verifies_legacy_hashes() {
+        let stored = "*2470C0C06DEE42FD1618BB99005ADCA2EC9D1E19";
+
+        assert_eq!(
+            verify("password", stored).unwrap(),
+            Verification::ValidNeedsRehash
+        );
+        assert_eq!(verify("Password", stored).unwrap(), Verification::Invalid);
+    }
+
+    #[test]
+    fn it_verifies_new_hashes() {
+        let stored = hash("password").unwrap();
+
+        assert!(stored.starts_with("$2b$12$"));
+        assert!(stored.len() <= 64);
+        assert_eq!(verify("password", &stored).unwrap(), Verification::Valid);
+        assert_eq!(verify("hunter2", &stored).unwrap(), Verification::Invalid);
+    }
+
+    #[test]
+    fn it_rejects_unknown_formats() {
+        assert_eq!(verify("", "").unwrap(), Verification::Invalid);
+        assert_eq!(
+            verify("password", "password").unwrap(),
+            Verification::Invalid
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Result};
+use spdlog::prelude::*;
+use tokio::io::{stdin, AsyncBufReadExt, BufReader};
+
+use crate::{ip_log, maintenance, LoginContext};
+
+const HELP: &str = r#"Commands:
+  ban <account id> <duration|permanent> <reason>
+      Bans an account, e.g. `ban 1000 7d spamming`. Durations take an
+      s, m, h or d suffix.
+  unban <account id>
+      Lifts the ban on an account.
+  ip <address>
+      Lists the accounts that have logged in from an address. Requires
+      login.LOG_USER_IP.
+  restore <character id>
+      Restores a character deleted from the lobby.
+  purge <character id>
+      Removes a deleted character for good.
+  maint <on|off>
+      Toggles maintenance mode, in which only GM accounts can log in.
+  optimize
+      Runs OPTIMIZE TABLE over the account and character tables, which
+      locks each table while it runs.
+  help
+      Shows this message."#;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Ban {
+        acc_id: u32,
+        duration: Option<Duration>,
+        reason: String,
+    },
+    Unban {
+        acc_id: u32,
+    },
+    Ip(IpAddr),
+    Restore {
+        char_id: u32,
+    },
+    Purge {
+        char_id: u32,
+    },
+    Maint(bool),
+    Optimize,
+    Help,
+}
+
+/// Reads admin commands from stdin until it is closed.
+pub async fn run(ctx: Arc<LoginContext>) -> Result<()> {
+    let mut lines = BufReader::new(stdin()).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = match parse(&line) {
+            Ok(command) => execute(&ctx, command).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = result {
+            error!(logger: ctx.logger, "{}", err);
+        }
+    }
+
+    Ok(())
+}
+
+async fn execute(ctx: &LoginContext, command: Command) -> Result<()> {
+    match command {
+        Command::Ban {
+            acc_id,
+            duration,
+            reason,
+        } => {
+            let until = match duration {
+                Some(duration) => Some(
+                    SystemTime::now()
+                        .checked_add(duration)
+                        .ok_or_else(|| anyhow!("Ban duration is too long."))?,
+                ),
+                None => None,
+            };
+            ctx.accounts.ban_account(acc_id, &reason, until).await?;
+            info!(logger: ctx.logger, "Banned account {}.", acc_id);
+        }
+        Command::Unban { acc_id } => {
+            ctx.accounts.unban_account(acc_id).await?;
+            info!(logger: ctx.logger, "Unbanned account {}.", acc_id);
+        }
+        Command::Ip(addr) => {
+            let accounts = ip_log::accounts_from(&ctx.pool, addr).await?;
+
+            if accounts.is_empty() {
+                info!(logger: ctx.logger, "No logins recorded from {}.", addr);
+            }
+
+            for (acc_id, login, last_login) in accounts {
+                info!(
+                    logger: ctx.logger,
+                    "{} {} (last login {})",
+                    acc_id,
+                    login,
+                    last_login
+                );
+            }
+        }
+        Command::Restore { char_id } => {
+            ctx.characters.restore_character(char_id).await??;
+            info!(logger: ctx.logger, "Restored character {}.", char_id);
+        }
+        Command::Purge { char_id } => {
+            ctx.characters.purge_character(char_id).await??;
+            info!(logger: ctx.logger, "Purged character {}.", char_id);
+        }
+        Command::Maint(enable) => {
+            ctx.set_maint_mode(enable);
+            info!(
+                logger: ctx.logger,
+                "Maintenance mode {}.",
+                if enable { "enabled" } else { "disabled" }
+            );
+        }
+        Command::Optimize => {
+            maintenance::optimize_tables(&ctx.pool, &ctx.logger).await;
+        }
+        Command::Help => info!(logger: ctx.logger, "{}", HELP),
+    }
+
+    Ok(())
+}
+
+fn parse(line: &str) -> Result<Command> {
+    let mut words = line.split_whitespace();
+
+    match words.next() {
+        Some("ban") => {
+            let acc_id = parse_acc_id(words.next())?;
+            let duration = match words.next() {
+                Some("permanent") => None,
+                Some(duration) => Some(parse_duration(duration)?),
+                None => bail!("Missing ban duration. Type `help` for usage."),
+            };
+            let reason = words.collect::<Vec<_>>().join(" ");
+
+            Ok(Command::Ban {
+                acc_id,
+                duration,
+                reason,
+            })
+        }
+        Some("unban") => Ok(Command::Unban {
+            acc_id: parse_acc_id(words.next())?,
+        }),
+        Some("ip") => words
+            .next()
+            .and_then(|addr| addr.parse().ok())
+            .map(Command::Ip)
+            .ok_or_else(|| anyhow!("Usage: ip <address>")),
+        Some("restore") => Ok(Command::Restore {
+            char_id: parse_char_id(words.next())?,
+        }),
+        Some("purge") => Ok(Command::Purge {
+            char_id: parse_char_id(words.next())?,
+        }),
+        Some("maint") => match words.next() {
+            Some("on") => Ok(Command::Maint(true)),
+            Some("off") => Ok(Command::Maint(false)),
+            _ => bail!("Usage: maint <on|off>"),
+        },
+        Some("optimize") => Ok(Command::Optimize),
+        Some("help") => Ok(Command::Help),
+        Some(command) => {
+            bail!("Unknown command `{}`. Type `help` for usage.", command)
+        }
+        None => bail!("Empty command."),
+    }
+}
+
+fn parse_acc_id(word: Option<&str>) -> Result<u32> {
+    word.ok_or_else(|| anyhow!("Missing account id."))?
+        .parse()
+        .map_err(|_| anyhow!("Invalid account id."))
+}
+
+fn parse_char_id(word: Option<&str>) -> Result<u32> {
+    word.ok_or_else(|| anyhow!("Missing character id."))?
+        .parse()
+        .map_err(|_| anyhow!("Invalid character id."))
+}
+
+fn parse_duration(word: &str) -> Result<Duration> {
+    let split = word.char_indices().last().map_or(0, |(i, _)| i);
+    let (amount, unit) = word.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration `{}`.", word))?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => bail!("Invalid duration unit in `{}`.", word),
+    };
+
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| anyhow!("Duration `{}` is too long.", word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{AccountStore, MemoryStore};
+
+    #[test]
+    fn it_parses_ban() {
+        assert_eq!(
+            parse("ban 1000 7d spamming the lobby").unwrap(),
+            Command::Ban {
+                acc_id: 1000,
+                duration: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+                reason: "spamming the lobby".to_owned(),
+            }
+        );
+
+        assert_eq!(
+            parse("ban 1000 permanent").unwrap(),
+            Command::Ban {
+                acc_id: 1000,
+                duration: None,
+                reason: "".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn it_parses_unban() {
+        assert_eq!(
+            parse("unban 1000").unwrap(),
+            Command::Unban { acc_id: 1000 }
+        );
+    }
+
+    #[test]
+    fn it_parses_ip() {
+        assert_eq!(
+            parse("ip 10.0.0.1").unwrap(),
+            Command::Ip("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            parse("ip 2001:db8::1").unwrap(),
+            Command::Ip("2001:db8::1".parse().unwrap())
+        );
+        assert!(parse("ip").is_err());
+        assert!(parse("ip localhost").is_err());
+    }
+
+    #[test]
+    fn it_parses_restore_and_purge() {
+        assert_eq!(
+            parse("restore 21828").unwrap(),
+            Command::Restore { char_id: 21828 }
+        );
+        assert_eq!(
+            parse("purge 21828").unwrap(),
+            Command::Purge { char_id: 21828 }
+        );
+        assert!(parse("restore").is_err());
+        assert!(parse("purge Shantotto").is_err());
+    }
+
+    #[test]
+    fn it_parses_maint() {
+        assert_eq!(parse("maint on").unwrap(), Command::Maint(true));
+        assert_eq!(parse("maint off").unwrap(), Command::Maint(false));
+        assert!(parse("maint").is_err());
+    }
+
+    #[test]
+    fn it_parses_optimize() {
+        assert_eq!(parse("optimize").unwrap(), Command::Optimize);
+    }
+
+    #[test]
+    fn it_rejects_invalid_durations() {
+        assert!(parse("ban 1000 5é x").is_err());
+        assert!(parse("ban 1000 é").is_err());
+        assert!(parse("ban 1000 18446744073709551615d").is_err());
+        assert_eq!(
+            parse_duration("18446744073709551615s").unwrap(),
+            Duration::from_secs(u64::MAX)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_bans_past_the_end_of_time() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = LoginContext::for_tests(store.clone());
+        let ban = Command::Ban {
+            acc_id: 1000,
+            duration: Some(Duration::from_secs(u64::MAX)),
+            reason: "".to_owned(),
+        };
+
+        assert!(execute(&ctx, ban).await.is_err());
+        assert_eq!(store.ban_active(1000).await.unwrap(), None);
+    }
+
+    #[test]
+    fn it_rejects_invalid_commands() {
+        assert!(parse("ban").is_err());
+        assert!(parse("ban abc 7d").is_err());
+        assert!(parse("ban 1000").is_err());
+        assert!(parse("ban 1000 7w").is_err());
+        assert!(parse("ban 1000 d").is_err());
+        assert!(parse("kick 1000").is_err());
+    }
+}
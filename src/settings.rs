@@ -53,6 +53,22 @@ impl<'lua> Settings<'lua> {
                 })
             })
     }
+
+    /// Reads a list of strings. A list set through an env var arrives as a
+    /// single string, e.g. `XI_LOGIN_BANNED_WORDS_LIST=foo,bar`, and is split
+    /// on commas.
+    pub fn try_get_list(&self, key: &str) -> Result<Vec<String>> {
+        match self.settings.get(key) {
+            Some(Value::String(s)) => Ok(s
+                .to_str()?
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_owned)
+                .collect()),
+            _ => self.try_get::<Vec<String>>(key),
+        }
+    }
 }
 
 /// Reads all lua files in the given directory and loads them into `lua`, sorted by name. Ignores non-lua files, if any.
@@ -245,6 +261,30 @@ mod tests {
         let value = settings.try_get::<bool>("main.FOO_BAR").unwrap();
         assert_eq!(value, false);
     }
+
+    #[test]
+    fn it_loads_list_settings() {
+        let lua = Lua::new().unwrap();
+        let settings = Settings::new(&lua).unwrap();
+        let value = settings.try_get_list("login.BANNED_WORDS_LIST").unwrap();
+        assert_eq!(value, vec!["badword".to_owned()]);
+        assert!(settings.try_get_list("main.SERVER_PORT_MISSING").is_err());
+    }
+
+    #[test]
+    fn it_loads_list_env_var() {
+        let _lock = lock_test();
+        let _env = set_env(
+            OsString::from("XI_LOGIN_BANNED_WORDS_LIST"),
+            "foo, bar,,baz",
+        );
+
+        let lua = Lua::new().unwrap();
+        let settings = Settings::new(&lua).unwrap();
+
+        let value = settings.try_get_list("login.BANNED_WORDS_LIST").unwrap();
+        assert_eq!(value, vec!["foo", "bar", "baz"]);
+    }
 }
 
 fn str_to_value<'lua>(lua: &'lua Lua, s: &str) -> Result<Value<'lua>> {
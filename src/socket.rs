@@ -1,20 +1,26 @@
-use anyhow::Result;
-use ipnetwork::Ipv4Network;
+use anyhow::{anyhow, Context, Result};
+use ipnetwork::{IpNetwork, Ipv6Network};
 use spdlog::prelude::*;
 use spdlog::{Logger, LoggerBuilder};
-use std::net::Ipv4Addr;
-use std::ops::Deref;
+use std::future::Future;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
 
+use crate::connect_history::{ConnectHistory, SystemClock};
 use crate::settings::Settings;
 
 struct SocketBuilder {
     enable_ip_rules: bool,
     stall_time: Duration,
     access_order: AccessOrder,
-    access_allow: Vec<Ipv4Network>,
-    access_deny: Vec<Ipv4Network>,
+    access_allow: Vec<IpNetwork>,
+    access_deny: Vec<IpNetwork>,
     connect_count: usize,
     connect_interval: Duration,
     connect_lockout: Duration,
@@ -44,9 +50,12 @@ impl SocketBuilder {
             access_order: self.access_order,
             access_allow: self.access_allow,
             access_deny: self.access_deny,
-            connect_count: self.connect_count,
-            connect_interval: self.connect_interval,
-            connect_lockout: self.connect_lockout,
+            connect_history: ConnectHistory::new(
+                self.connect_count,
+                self.connect_interval,
+                self.connect_lockout,
+                Arc::new(SystemClock),
+            ),
             logger: self.logger,
         }
     }
@@ -66,12 +75,12 @@ impl SocketBuilder {
         self
     }
 
-    fn access_allow(mut self, n: Vec<Ipv4Network>) -> Self {
+    fn access_allow(mut self, n: Vec<IpNetwork>) -> Self {
         self.access_allow = n;
         self
     }
 
-    fn access_deny(mut self, n: Vec<Ipv4Network>) -> Self {
+    fn access_deny(mut self, n: Vec<IpNetwork>) -> Self {
         self.access_deny = n;
         self
     }
@@ -101,46 +110,196 @@ pub struct Socket {
     enable_ip_rules: bool,
     stall_time: Duration,
     access_order: AccessOrder,
-    access_allow: Vec<Ipv4Network>,
-    access_deny: Vec<Ipv4Network>,
-    connect_count: usize,
-    connect_interval: Duration,
-    connect_lockout: Duration,
+    access_allow: Vec<IpNetwork>,
+    access_deny: Vec<IpNetwork>,
+    connect_history: ConnectHistory,
     logger: Logger,
 }
 
 impl Socket {
-    pub fn new(
-        enable_ip_rules: bool,
-        stall_time: Duration,
-        access_order: AccessOrder,
-        access_allow: Vec<Ipv4Network>,
-        access_deny: Vec<Ipv4Network>,
-        connect_count: usize,
-        connect_interval: Duration,
-        connect_lockout: Duration,
-        logger: Logger,
-    ) -> Socket {
-        Socket {
-            enable_ip_rules,
-            stall_time,
-            access_order,
-            access_allow,
-            access_deny,
-            connect_count,
-            connect_interval,
-            connect_lockout,
-            logger,
+    fn builder() -> SocketBuilder {
+        SocketBuilder::new()
+    }
+
+    /// Fills `buf` from `stream`, failing if the peer stalls for longer than
+    /// `stall_time`.
+    pub async fn read_exact(
+        &self,
+        stream: &mut (impl AsyncRead + Unpin),
+        buf: &mut [u8],
+    ) -> Result<()> {
+        self.stall_timeout(stream.read_exact(buf)).await??;
+
+        Ok(())
+    }
+
+    /// Runs a read on a socket, failing if the peer stalls for longer than
+    /// `stall_time`.
+    pub async fn stall_timeout<F: Future>(&self, read: F) -> Result<F::Output> {
+        tokio::time::timeout(self.stall_time, read)
+            .await
+            .map_err(|_| {
+                anyhow!("socket stalled for more than {:?}", self.stall_time)
+            })
+    }
+
+    /// Decides whether a connection from `addr` may proceed, according to
+    /// `access_order` and the allow and deny lists.
+    pub fn check_ip(&self, addr: IpAddr) -> IpDecision {
+        if !self.enable_ip_rules {
+            return IpDecision::Accept;
+        }
+
+        let is_allowed = matches_access_list(&self.access_allow, addr);
+        let is_denied = matches_access_list(&self.access_deny, addr);
+
+        match self.access_order {
+            AccessOrder::DenyAllow => {
+                if is_denied {
+                    IpDecision::Reject
+                } else if is_allowed {
+                    IpDecision::AcceptUnconditionally
+                } else {
+                    IpDecision::Accept
+                }
+            }
+            AccessOrder::AllowDeny => {
+                if is_allowed {
+                    IpDecision::AcceptUnconditionally
+                } else if is_denied {
+                    IpDecision::Reject
+                } else {
+                    IpDecision::Accept
+                }
+            }
+            AccessOrder::MutualFailure => {
+                if is_allowed && !is_denied {
+                    IpDecision::AcceptUnconditionally
+                } else {
+                    IpDecision::Reject
+                }
+            }
+        }
+    }
+
+    /// Applies the IP rules and flood protection to a new connection from
+    /// `addr`, recording the attempt.
+    pub fn accepts(&self, addr: IpAddr) -> bool {
+        if !self.enable_ip_rules {
+            return true;
+        }
+
+        let addr = addr.to_canonical();
+
+        match self.check_ip(addr) {
+            IpDecision::Reject => false,
+            decision => {
+                let allowed = self.connect_history.record(
+                    addr,
+                    decision == IpDecision::AcceptUnconditionally,
+                );
+
+                if !allowed {
+                    warn!(
+                        logger: self.logger,
+                        "Connection flood detected from {}", addr
+                    );
+                }
+
+                allowed
+            }
         }
     }
 }
 
-impl Socket {
-    fn builder() -> SocketBuilder {
-        SocketBuilder::new()
+fn matches_access_list(list: &[IpNetwork], addr: IpAddr) -> bool {
+    // Peers on a dual-stack listener show up as IPv4-mapped IPv6 addresses.
+    let addr = addr.to_canonical();
+
+    list.iter().any(|network| match (network, addr) {
+        (IpNetwork::V4(network), IpAddr::V4(addr)) => network.contains(addr),
+        (IpNetwork::V6(network), IpAddr::V4(addr)) => {
+            network.contains(addr.to_ipv6_mapped())
+        }
+        (IpNetwork::V6(network), IpAddr::V6(addr)) => network.contains(addr),
+        (IpNetwork::V4(_), IpAddr::V6(_)) => false,
+    })
+}
+
+/// Outcome of checking a peer address against the IP rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpDecision {
+    Reject,
+    Accept,
+    /// Matched the allow list, which also exempts it from flood protection.
+    AcceptUnconditionally,
+}
+
+/// Binds a listener on `ip`, which may be an IPv4 or IPv6 address such as
+/// `0.0.0.0` or `::`.
+pub async fn bind(ip: &str, port: u16) -> Result<TcpListener> {
+    TcpListener::bind((ip, port))
+        .await
+        .with_context(|| format!("Could not listen on {}:{}", ip, port))
+}
+
+/// Hands the write half of a connection to a task that sends whatever is
+/// queued on the returned channel, so that other connections can write to it.
+/// The task ends when every sender has been dropped or the peer goes away.
+pub fn spawn_writer(
+    mut writer: OwnedWriteHalf,
+) -> mpsc::UnboundedSender<Vec<u8>> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+
+    tokio::spawn(async move {
+        while let Some(bytes) = rx.recv().await {
+            if writer.write_all(&bytes).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    tx
+}
+
+/// Accepts connections on `listener` forever, handling each one in its own
+/// task so that a slow client cannot hold up the others.
+pub async fn serve<F, Fut>(
+    listener: TcpListener,
+    socket: Arc<Socket>,
+    handler: F,
+) -> Result<()>
+where
+    F: Fn(TcpStream, SocketAddr) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    loop {
+        let (stream, addr) = listener.accept().await?;
+
+        if !socket.accepts(addr.ip()) {
+            info!(logger: socket.logger, "Rejected connection from {}", addr);
+            continue;
+        }
+
+        debug!(logger: socket.logger, "Accepted connection from {}", addr);
+
+        let connection = handler(stream, addr);
+        let logger = socket.logger.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                error!(
+                    logger: logger,
+                    "Connection from {} failed: {:?}",
+                    addr,
+                    err
+                );
+            }
+        });
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub enum AccessOrder {
     DenyAllow,
     AllowDeny,
@@ -167,7 +326,7 @@ impl AccessOrder {
     }
 }
 
-fn socket_init_tcp(
+pub fn socket_init_tcp(
     mut log_builder: LoggerBuilder,
     settings: &Settings,
 ) -> Result<Socket> {
@@ -212,19 +371,11 @@ fn socket_init_tcp(
         .build())
 }
 
-fn create_session(
-    fd: u64,
-    recv: impl Fn(u64) -> u64,
-    send: impl Fn(u64) -> u64,
-    parse: impl Fn(u64) -> u64,
-) -> () {
-}
-
 fn load_access_list(
     kind: AccessKind,
     access_list: &str,
     logger: &Logger,
-) -> Vec<Ipv4Network> {
+) -> Vec<IpNetwork> {
     let kind_str = if kind == AccessKind::Allow {
         "allow"
     } else {
@@ -233,9 +384,10 @@ fn load_access_list(
 
     info!(logger: logger, "Loading {} access list...", kind_str);
 
-    let result: Vec<Ipv4Network> = access_list
+    let result: Vec<IpNetwork> = access_list
         .split(',')
-        .filter(|x| x.deref() != "")
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
         .filter_map(|x| access_ipmask(x, logger))
         .collect();
 
@@ -249,12 +401,16 @@ fn load_access_list(
     result
 }
 
-fn access_ipmask(s: &str, logger: &Logger) -> Option<Ipv4Network> {
+/// Parses a single access list entry. `all` is the whole IPv6 space, which
+/// also covers every IPv4 address through its mapped form.
+fn access_ipmask(s: &str, logger: &Logger) -> Option<IpNetwork> {
     if s == "all" {
-        return Ipv4Network::new(Ipv4Addr::new(0, 0, 0, 0), 0).ok();
+        return Ipv6Network::new(Ipv6Addr::UNSPECIFIED, 0)
+            .ok()
+            .map(IpNetwork::V6);
     }
 
-    let result = Ipv4Network::from_str(s);
+    let result = IpNetwork::from_str(s);
 
     match result {
         Ok(network) => info!(
@@ -275,6 +431,9 @@ fn access_ipmask(s: &str, logger: &Logger) -> Option<Ipv4Network> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::packets::login::{LoginReply, LoginRequest};
+    use crate::store::{AccountStore, MemoryStore};
+    use crate::LoginContext;
 
     fn logger() -> Logger {
         Logger::builder().build().unwrap()
@@ -285,6 +444,206 @@ mod tests {
         Socket::builder().build();
     }
 
+    async fn spawn_server(socket: Socket) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let socket = Arc::new(socket);
+
+        tokio::spawn(serve(listener, socket.clone(), move |mut stream, _| {
+            let socket = socket.clone();
+            async move {
+                let mut buffer = [0; 33];
+                socket.read_exact(&mut stream, &mut buffer).await?;
+                stream.write_all(&[0x01]).await?;
+                Ok(())
+            }
+        }));
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn it_serves_clients_while_another_stalls() {
+        let store = Arc::new(MemoryStore::default());
+        let hash = bcrypt::hash("password", 4).unwrap();
+        let acc_id = store
+            .create_account("shantotto", &hash)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = Arc::new(LoginContext::for_tests(store));
+        tokio::spawn(crate::do_init(listener, ctx));
+
+        let _stalled = TcpStream::connect(addr).await.unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let request = LoginRequest::Attempt {
+            name: "shantotto".to_owned(),
+            password: "password".to_owned(),
+        };
+        client.write_all(&request.encode().unwrap()).await.unwrap();
+
+        let mut reply = Vec::new();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            client.read_to_end(&mut reply),
+        )
+        .await
+        .expect("second client was blocked by the stalled one")
+        .unwrap();
+
+        assert_eq!(
+            LoginReply::decode(&reply).unwrap(),
+            LoginReply::Success(acc_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_drops_stalled_clients() {
+        let addr = spawn_server(
+            Socket::builder()
+                .stall_time(Duration::from_millis(50))
+                .build(),
+        )
+        .await;
+
+        let mut stalled = TcpStream::connect(addr).await.unwrap();
+
+        let mut buffer = [0; 1];
+        let read = tokio::time::timeout(
+            Duration::from_secs(5),
+            stalled.read(&mut buffer),
+        )
+        .await
+        .expect("stalled client was not disconnected")
+        .unwrap();
+
+        assert_eq!(read, 0);
+    }
+
+    #[test]
+    fn it_checks_ip_against_access_order() {
+        let allow = "127.0.0.1,192.168.0.0/16";
+        let deny = "192.168.1.0/24,10.0.0.0/8";
+
+        let local = "127.0.0.1";
+        let lan = "192.168.0.5";
+        let both = "192.168.1.5";
+        let denied = "10.1.2.3";
+        let other = "8.8.8.8";
+
+        use IpDecision::*;
+
+        let cases = [
+            (AccessOrder::DenyAllow, local, AcceptUnconditionally),
+            (AccessOrder::DenyAllow, lan, AcceptUnconditionally),
+            (AccessOrder::DenyAllow, both, Reject),
+            (AccessOrder::DenyAllow, denied, Reject),
+            (AccessOrder::DenyAllow, other, Accept),
+            (AccessOrder::AllowDeny, local, AcceptUnconditionally),
+            (AccessOrder::AllowDeny, lan, AcceptUnconditionally),
+            (AccessOrder::AllowDeny, both, AcceptUnconditionally),
+            (AccessOrder::AllowDeny, denied, Reject),
+            (AccessOrder::AllowDeny, other, Accept),
+            (AccessOrder::MutualFailure, local, AcceptUnconditionally),
+            (AccessOrder::MutualFailure, lan, AcceptUnconditionally),
+            (AccessOrder::MutualFailure, both, Reject),
+            (AccessOrder::MutualFailure, denied, Reject),
+            (AccessOrder::MutualFailure, other, Reject),
+        ];
+
+        for (order, addr, expected) in cases {
+            let socket = Socket::builder()
+                .access_order(order)
+                .access_allow(load_access_list(
+                    AccessKind::Allow,
+                    allow,
+                    &logger(),
+                ))
+                .access_deny(load_access_list(
+                    AccessKind::Deny,
+                    deny,
+                    &logger(),
+                ))
+                .build();
+
+            assert_eq!(
+                socket.check_ip(addr.parse().unwrap()),
+                expected,
+                "{:?} {}",
+                order,
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn it_accepts_everything_without_ip_rules() {
+        let socket = Socket::builder()
+            .ip_rules(false)
+            .access_order(AccessOrder::MutualFailure)
+            .access_deny(load_access_list(AccessKind::Deny, "all", &logger()))
+            .build();
+
+        assert_eq!(
+            socket.check_ip("10.0.0.1".parse().unwrap()),
+            IpDecision::Accept
+        );
+    }
+
+    #[tokio::test]
+    async fn it_closes_rejected_connections() {
+        let addr = spawn_server(
+            Socket::builder()
+                .access_deny(load_access_list(
+                    AccessKind::Deny,
+                    "127.0.0.1",
+                    &logger(),
+                ))
+                .build(),
+        )
+        .await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut buffer = [0; 1];
+        let read = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.read(&mut buffer),
+        )
+        .await
+        .expect("rejected client was not disconnected")
+        .unwrap_or(0);
+
+        assert_eq!(read, 0);
+    }
+
+    #[test]
+    fn it_locks_out_connection_floods() {
+        let socket = Socket::builder()
+            .connect_count(3)
+            .access_allow(load_access_list(
+                AccessKind::Allow,
+                "127.0.0.1",
+                &logger(),
+            ))
+            .build();
+
+        let flooder = "10.0.0.1".parse().unwrap();
+        let allowed = "127.0.0.1".parse().unwrap();
+
+        for _ in 0..2 {
+            assert!(socket.accepts(flooder));
+        }
+        assert!(!socket.accepts(flooder));
+
+        for _ in 0..10 {
+            assert!(socket.accepts(allowed));
+        }
+    }
+
     #[test]
     fn it_parses_access_list() {
         assert_eq!(
@@ -304,16 +663,53 @@ mod tests {
     fn it_parses_ip_range() {
         assert_eq!(
             access_ipmask("all", &logger()),
-            Some(Ipv4Network::new(Ipv4Addr::new(0, 0, 0, 0), 0).unwrap())
+            Some(IpNetwork::V6(
+                Ipv6Network::new(Ipv6Addr::UNSPECIFIED, 0).unwrap()
+            ))
         );
 
         assert!(access_ipmask("127.0.0.1", &logger()).is_some());
         assert_eq!(
             access_ipmask("127.0.0.1", &logger()).unwrap().mask(),
-            Ipv4Addr::new(255, 255, 255, 255)
+            "255.255.255.255".parse::<IpAddr>().unwrap()
         );
 
         assert!(access_ipmask("192.168.0.0/16", &logger()).is_some());
         assert!(access_ipmask("10.0.0.0/255.0.0.0", &logger()).is_some());
+        assert!(access_ipmask("2001:db8::/32", &logger()).is_some());
+        assert!(access_ipmask("::1", &logger()).is_some());
+        assert!(access_ipmask("2001:db8::/129", &logger()).is_none());
+    }
+
+    #[test]
+    fn it_checks_ipv6_addresses() {
+        let socket = Socket::builder()
+            .access_order(AccessOrder::MutualFailure)
+            .access_allow(load_access_list(AccessKind::Allow, "all", &logger()))
+            .access_deny(load_access_list(
+                AccessKind::Deny,
+                "2001:db8::/32,10.0.0.0/8",
+                &logger(),
+            ))
+            .build();
+
+        let cases = [
+            ("2001:db8::1", IpDecision::Reject),
+            ("2001:db9::1", IpDecision::AcceptUnconditionally),
+            ("::1", IpDecision::AcceptUnconditionally),
+            ("10.0.0.1", IpDecision::Reject),
+            ("::ffff:10.0.0.1", IpDecision::Reject),
+            ("::ffff:192.168.0.1", IpDecision::AcceptUnconditionally),
+            ("192.168.0.1", IpDecision::AcceptUnconditionally),
+        ];
+
+        for (addr, expected) in cases {
+            assert_eq!(
+                socket.check_ip(addr.parse().unwrap()),
+                expected,
+                "{}",
+                addr
+            );
+        }
     }
 }
@@ -0,0 +1,494 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+
+use super::{Account, AccountStore, CharacterStore, GameSession, WorldEntry};
+use crate::characters::{
+    CharacterEntry, DeletionError, Nation, NewCharacter, StartConfig,
+    MAX_CHARACTERS,
+};
+use crate::{
+    ACCOUNT_ID_MIN, ACCOUNT_PRIVILEGE_CODE_ADMIN, ACCOUNT_PRIVILEGE_CODE_USER,
+    ACCOUNT_STATUS_CODE_BANNED, ACCOUNT_STATUS_CODE_NORMAL,
+};
+
+/// The account that tests log in with, the first one created.
+pub const TEST_ACC_ID: u32 = ACCOUNT_ID_MIN;
+
+/// Where tests connect from.
+pub fn test_client() -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, 1], 50000))
+}
+
+/// The character that tests create.
+pub fn test_character() -> NewCharacter {
+    NewCharacter {
+        name: "Shantotto".to_owned(),
+        race: 5,
+        main_job: 4,
+        nation: Nation::Windurst,
+        size: 0,
+        face: 2,
+    }
+}
+
+/// Stores that keep everything in memory, for tests.
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    accounts: BTreeMap<u32, StoredAccount>,
+    /// Ban end times by account, `None` for permanent bans.
+    bans: HashMap<u32, Option<SystemTime>>,
+    game_sessions: HashMap<u32, GameSession>,
+    characters: BTreeMap<u32, StoredCharacter>,
+    mob_npc_names: Vec<String>,
+    zone_servers: HashMap<u16, SocketAddrV4>,
+}
+
+struct StoredAccount {
+    login: String,
+    password: String,
+    status: u32,
+    privilege: u32,
+}
+
+struct StoredCharacter {
+    acc_id: u32,
+    entry: CharacterEntry,
+    gm_level: u8,
+    deleted: bool,
+}
+
+impl MemoryStore {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Gives a name to a mob or NPC, taking it from characters.
+    pub fn add_mob_or_npc_name(&self, name: &str) {
+        self.state().mob_npc_names.push(name.to_owned());
+    }
+
+    /// Starts a map server for `zone`.
+    pub fn set_zone_server(&self, zone: u16, server: SocketAddrV4) {
+        self.state().zone_servers.insert(zone, server);
+    }
+
+    pub fn set_gm_level(&self, char_id: u32, gm_level: u8) {
+        if let Some(character) = self.state().characters.get_mut(&char_id) {
+            character.gm_level = gm_level;
+        }
+    }
+
+    pub fn set_admin(&self, acc_id: u32) {
+        if let Some(account) = self.state().accounts.get_mut(&acc_id) {
+            account.privilege |= ACCOUNT_PRIVILEGE_CODE_ADMIN;
+        }
+    }
+}
+
+#[async_trait]
+impl AccountStore for MemoryStore {
+    async fn find_account(&self, login: &str) -> Result<Option<Account>> {
+        Ok(self
+            .state()
+            .accounts
+            .iter()
+            .find(|(_, account)| account.login == login)
+            .map(|(&acc_id, account)| Account {
+                acc_id,
+                status: account.status,
+                password: account.password.clone(),
+            }))
+    }
+
+    async fn create_account(
+        &self,
+        login: &str,
+        hash: &str,
+    ) -> Result<Option<u32>> {
+        let mut state = self.state();
+
+        if state
+            .accounts
+            .values()
+            .any(|account| account.login == login)
+        {
+            return Ok(None);
+        }
+
+        let acc_id = state
+            .accounts
+            .keys()
+            .next_back()
+            .map_or(ACCOUNT_ID_MIN, |id| id + 1)
+            .max(ACCOUNT_ID_MIN);
+
+        state.accounts.insert(
+            acc_id,
+            StoredAccount {
+                login: login.to_owned(),
+                password: hash.to_owned(),
+                status: ACCOUNT_STATUS_CODE_NORMAL,
+                privilege: ACCOUNT_PRIVILEGE_CODE_USER,
+            },
+        );
+
+        Ok(Some(acc_id))
+    }
+
+    async fn set_password(&self, acc_id: u32, hash: &str) -> Result<()> {
+        if let Some(account) = self.state().accounts.get_mut(&acc_id) {
+            account.password = hash.to_owned();
+        }
+        Ok(())
+    }
+
+    async fn touch_account(&self, _acc_id: u32) -> Result<()> {
+        Ok(())
+    }
+
+    async fn is_gm_account(&self, acc_id: u32) -> Result<bool> {
+        let state = self.state();
+        let Some(account) = state.accounts.get(&acc_id) else {
+            return Ok(false);
+        };
+
+        Ok(account.privilege & ACCOUNT_PRIVILEGE_CODE_ADMIN > 0
+            || state.characters.values().any(|character| {
+                character.acc_id == acc_id
+                    && character.gm_level > 0
+                    && !character.deleted
+            }))
+    }
+
+    async fn ban_account(
+        &self,
+        acc_id: u32,
+        _reason: &str,
+        until: Option<SystemTime>,
+    ) -> Result<()> {
+        let mut state = self.state();
+        state.bans.insert(acc_id, until);
+        if let Some(account) = state.accounts.get_mut(&acc_id) {
+            account.status = ACCOUNT_STATUS_CODE_BANNED;
+        }
+        Ok(())
+    }
+
+    async fn unban_account(&self, acc_id: u32) -> Result<()> {
+        let mut state = self.state();
+        state.bans.remove(&acc_id);
+        if let Some(account) = state.accounts.get_mut(&acc_id) {
+            if account.status & ACCOUNT_STATUS_CODE_BANNED > 0 {
+                account.status = ACCOUNT_STATUS_CODE_NORMAL;
+            }
+        }
+        Ok(())
+    }
+
+    async fn ban_active(&self, acc_id: u32) -> Result<Option<bool>> {
+        Ok(self.state().bans.get(&acc_id).map(|until| match until {
+            Some(until) => *until > SystemTime::now(),
+            None => true,
+        }))
+    }
+
+    async fn game_session(&self, acc_id: u32) -> Result<Option<GameSession>> {
+        Ok(self.state().game_sessions.get(&acc_id).cloned())
+    }
+
+    async fn game_sessions_from(
+        &self,
+        client_addr: Ipv4Addr,
+    ) -> Result<Vec<u32>> {
+        Ok(self
+            .state()
+            .game_sessions
+            .values()
+            .filter(|session| session.client_addr == u32::from(client_addr))
+            .map(|session| session.acc_id)
+            .collect())
+    }
+
+    async fn start_game_session(&self, session: &GameSession) -> Result<()> {
+        self.state()
+            .game_sessions
+            .insert(session.acc_id, session.clone());
+        Ok(())
+    }
+
+    async fn end_game_session(&self, acc_id: u32) -> Result<()> {
+        self.state().game_sessions.remove(&acc_id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CharacterStore for MemoryStore {
+    async fn list_characters(
+        &self,
+        acc_id: u32,
+    ) -> Result<Vec<CharacterEntry>> {
+        Ok(self
+            .state()
+            .characters
+            .values()
+            .filter(|character| {
+                character.acc_id == acc_id && !character.deleted
+            })
+            .map(|character| character.entry.clone())
+            .take(MAX_CHARACTERS)
+            .collect())
+    }
+
+    async fn name_taken(&self, name: &str) -> Result<bool> {
+        Ok(self
+            .state()
+            .characters
+            .values()
+            .any(|character| character.entry.name == name))
+    }
+
+    async fn is_mob_or_npc_name(&self, name: &str) -> Result<bool> {
+        let name = name.to_uppercase();
+        Ok(self
+            .state()
+            .mob_npc_names
+            .iter()
+            .any(|other| other.to_uppercase().replace(['_', ' '], "") == name))
+    }
+
+    async fn create_character(
+        &self,
+        _config: &StartConfig,
+        acc_id: u32,
+        character: &NewCharacter,
+    ) -> Result<u32> {
+        if character.starting_job().is_none() {
+            bail!("Invalid starting job {}", character.main_job);
+        }
+
+        let mut state = self.state();
+        let char_id =
+            state.characters.keys().next_back().map_or(1, |id| id + 1);
+
+        state.characters.insert(
+            char_id,
+            StoredCharacter {
+                acc_id,
+                entry: CharacterEntry {
+                    char_id,
+                    name: character.name.clone(),
+                    zone: character.nation.start_zone(char_id),
+                    main_job: character.main_job,
+                    main_job_level: 1,
+                    race: character.race,
+                    face: character.face,
+                    size: character.size,
+                    look: [0; 7],
+                },
+                gm_level: 0,
+                deleted: false,
+            },
+        );
+
+        Ok(char_id)
+    }
+
+    async fn delete_character(
+        &self,
+        acc_id: u32,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>> {
+        let mut state = self.state();
+        let online = is_online(&state, char_id);
+
+        match state.characters.get_mut(&char_id) {
+            Some(character)
+                if character.acc_id == acc_id && !character.deleted =>
+            {
+                if online {
+                    return Ok(Err(DeletionError::Online));
+                }
+                character.deleted = true;
+                Ok(Ok(()))
+            }
+            _ => Ok(Err(DeletionError::NotFound)),
+        }
+    }
+
+    async fn restore_character(
+        &self,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>> {
+        match self.state().characters.get_mut(&char_id) {
+            Some(character) if character.deleted => {
+                character.deleted = false;
+                Ok(Ok(()))
+            }
+            _ => Ok(Err(DeletionError::NotFound)),
+        }
+    }
+
+    async fn purge_character(
+        &self,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>> {
+        let mut state = self.state();
+
+        match state.characters.get(&char_id) {
+            Some(character) if character.deleted => {}
+            _ => return Ok(Err(DeletionError::NotFound)),
+        }
+
+        if is_online(&state, char_id) {
+            return Ok(Err(DeletionError::Online));
+        }
+
+        state.characters.remove(&char_id);
+        Ok(Ok(()))
+    }
+
+    async fn world_entry(
+        &self,
+        acc_id: u32,
+        char_id: u32,
+    ) -> Result<Option<WorldEntry>> {
+        let state = self.state();
+
+        let Some(character) = state
+            .characters
+            .get(&char_id)
+            .filter(|c| c.acc_id == acc_id && !c.deleted)
+        else {
+            return Ok(None);
+        };
+
+        Ok(state
+            .zone_servers
+            .get(&character.entry.zone)
+            .map(|&zone_server| WorldEntry {
+                char_name: character.entry.name.clone(),
+                gm_level: character.gm_level,
+                zone_server,
+            }))
+    }
+}
+
+fn is_online(state: &State, char_id: u32) -> bool {
+    state
+        .game_sessions
+        .values()
+        .any(|session| session.char_id == char_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::Lua;
+    use crate::settings::Settings;
+
+    async fn add_character(store: &MemoryStore, acc_id: u32) -> u32 {
+        let lua = Lua::new().unwrap();
+        let config =
+            StartConfig::from_settings(&Settings::new(&lua).unwrap()).unwrap();
+        store
+            .create_character(&config, acc_id, &test_character())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_numbers_accounts_from_the_minimum() {
+        let store = MemoryStore::default();
+        assert_eq!(
+            store.create_account("a", "").await.unwrap(),
+            Some(TEST_ACC_ID)
+        );
+        assert_eq!(
+            store.create_account("b", "").await.unwrap(),
+            Some(TEST_ACC_ID + 1)
+        );
+        assert_eq!(store.create_account("a", "").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn it_restores_and_purges_characters() {
+        let store = MemoryStore::default();
+        let char_id = add_character(&store, TEST_ACC_ID).await;
+
+        assert_eq!(
+            store.delete_character(1001, char_id).await.unwrap(),
+            Err(DeletionError::NotFound)
+        );
+        assert_eq!(
+            store.purge_character(char_id).await.unwrap(),
+            Err(DeletionError::NotFound)
+        );
+
+        store
+            .delete_character(TEST_ACC_ID, char_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(store.name_taken("Shantotto").await.unwrap());
+        store.restore_character(char_id).await.unwrap().unwrap();
+        assert_eq!(store.list_characters(TEST_ACC_ID).await.unwrap().len(), 1);
+
+        store
+            .delete_character(TEST_ACC_ID, char_id)
+            .await
+            .unwrap()
+            .unwrap();
+        store.purge_character(char_id).await.unwrap().unwrap();
+        assert!(!store.name_taken("Shantotto").await.unwrap());
+        assert_eq!(
+            store.restore_character(char_id).await.unwrap(),
+            Err(DeletionError::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_keeps_characters_in_game() {
+        let store = MemoryStore::default();
+        let char_id = add_character(&store, TEST_ACC_ID).await;
+        let session = GameSession {
+            acc_id: TEST_ACC_ID,
+            char_id,
+            session_key: vec![0; 20],
+            server: "127.0.0.1:54230".parse().unwrap(),
+            client_addr: 0,
+            version_mismatch: false,
+        };
+
+        store.start_game_session(&session).await.unwrap();
+        assert_eq!(
+            store.delete_character(TEST_ACC_ID, char_id).await.unwrap(),
+            Err(DeletionError::Online)
+        );
+
+        store.end_game_session(TEST_ACC_ID).await.unwrap();
+        store
+            .delete_character(TEST_ACC_ID, char_id)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_matches_mob_and_npc_names() {
+        let store = MemoryStore::default();
+        store.add_mob_or_npc_name("Shadow_Lord");
+        assert!(store.is_mob_or_npc_name("ShadowLord").await.unwrap());
+        assert!(!store.is_mob_or_npc_name("Shadow").await.unwrap());
+    }
+}
@@ -0,0 +1,154 @@
+//! Storage of accounts and characters. The login logic only goes through
+//! these traits, so it runs the same against MySQL or, in tests, memory.
+
+#[cfg(test)]
+mod memory;
+mod mysql;
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::characters::{
+    CharacterEntry, DeletionError, NewCharacter, StartConfig,
+};
+
+#[cfg(test)]
+pub use memory::{test_character, test_client, MemoryStore, TEST_ACC_ID};
+pub use mysql::MySqlStore;
+
+/// An account as stored, with its password hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub acc_id: u32,
+    pub status: u32,
+    pub password: String,
+}
+
+/// A character that is in game, as recorded in `accounts_sessions` for the
+/// map server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameSession {
+    pub acc_id: u32,
+    pub char_id: u32,
+    pub session_key: Vec<u8>,
+    pub server: SocketAddrV4,
+    /// Only IPv4 clients are recorded, others are stored as 0.
+    pub client_addr: u32,
+    pub version_mismatch: bool,
+}
+
+/// What the lobby needs to send a character into the world.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorldEntry {
+    pub char_name: String,
+    pub gm_level: u8,
+    /// The map server of the zone the character is in.
+    pub zone_server: SocketAddrV4,
+}
+
+#[async_trait]
+pub trait AccountStore: Send + Sync {
+    async fn find_account(&self, login: &str) -> Result<Option<Account>>;
+
+    /// Creates an account with the next free id, which is never below
+    /// `ACCOUNT_ID_MIN`, and returns that id, or `None` if `login` is taken.
+    async fn create_account(
+        &self,
+        login: &str,
+        hash: &str,
+    ) -> Result<Option<u32>>;
+
+    async fn set_password(&self, acc_id: u32, hash: &str) -> Result<()>;
+
+    /// Marks the account as modified, e.g. by a login.
+    async fn touch_account(&self, acc_id: u32) -> Result<()>;
+
+    /// Whether the account has admin privileges or owns a GM character that
+    /// is not deleted.
+    async fn is_gm_account(&self, acc_id: u32) -> Result<bool>;
+
+    /// Bans an account until `until`, or forever if `until` is `None`.
+    /// Replaces any existing ban on the account.
+    async fn ban_account(
+        &self,
+        acc_id: u32,
+        reason: &str,
+        until: Option<SystemTime>,
+    ) -> Result<()>;
+
+    /// Lifts any ban on an account.
+    async fn unban_account(&self, acc_id: u32) -> Result<()>;
+
+    /// Whether the recorded ban of an account is still running, or `None`
+    /// if it has no ban recorded.
+    async fn ban_active(&self, acc_id: u32) -> Result<Option<bool>>;
+
+    async fn game_session(&self, acc_id: u32) -> Result<Option<GameSession>>;
+
+    /// Accounts with a character in game from `client_addr`.
+    async fn game_sessions_from(
+        &self,
+        client_addr: Ipv4Addr,
+    ) -> Result<Vec<u32>>;
+
+    /// Records a character entering the world, replacing any other session
+    /// of its account.
+    async fn start_game_session(&self, session: &GameSession) -> Result<()>;
+
+    async fn end_game_session(&self, acc_id: u32) -> Result<()>;
+}
+
+#[async_trait]
+pub trait CharacterStore: Send + Sync {
+    /// The characters of an account that are not deleted, in the order the
+    /// lobby shows them.
+    async fn list_characters(&self, acc_id: u32)
+        -> Result<Vec<CharacterEntry>>;
+
+    /// Whether a character uses `name`. Deleted characters keep their names
+    /// so that they can be restored.
+    async fn name_taken(&self, name: &str) -> Result<bool>;
+
+    /// Whether a mob or NPC goes by `name`. Their names use underscores or
+    /// spaces where a character name could not, so those are ignored.
+    async fn is_mob_or_npc_name(&self, name: &str) -> Result<bool>;
+
+    /// Creates a character for `acc_id` and returns its id. A failed
+    /// creation leaves nothing behind.
+    async fn create_character(
+        &self,
+        config: &StartConfig,
+        acc_id: u32,
+        character: &NewCharacter,
+    ) -> Result<u32>;
+
+    /// Deletes a character of `acc_id`. The character keeps its rows and
+    /// its name until it is purged, and can be restored until then.
+    async fn delete_character(
+        &self,
+        acc_id: u32,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>>;
+
+    /// Gives a deleted character back to its account.
+    async fn restore_character(
+        &self,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>>;
+
+    /// Removes a deleted character and everything it owns for good.
+    async fn purge_character(
+        &self,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>>;
+
+    /// Where a character of `acc_id` that is not deleted enters the world.
+    async fn world_entry(
+        &self,
+        acc_id: u32,
+        char_id: u32,
+    ) -> Result<Option<WorldEntry>>;
+}
@@ -0,0 +1,698 @@
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use mysql_async::{prelude::*, Pool, Row, Transaction};
+
+use super::{Account, AccountStore, CharacterStore, GameSession, WorldEntry};
+use crate::characters::{
+    self, CharacterEntry, DeletionError, NewCharacter, StartConfig,
+};
+use crate::{
+    ACCOUNT_ID_MIN, ACCOUNT_PRIVILEGE_CODE_ADMIN, ACCOUNT_PRIVILEGE_CODE_USER,
+    ACCOUNT_STATUS_CODE_BANNED, ACCOUNT_STATUS_CODE_NORMAL,
+};
+
+/// `timeunbann` used for bans without an end time.
+const PERMANENT_BAN_END: &str = "9999-12-31 23:59:59";
+
+/// Gil lives in the first slot of the inventory as an item.
+const GIL_ITEM_ID: u16 = 0xFFFF;
+
+/// Tables holding rows of a character besides `chars` itself, removed when
+/// it is purged.
+const CHARACTER_TABLES: [&str; 15] = [
+    "char_bazaar_msg",
+    "char_effects",
+    "char_equip",
+    "char_exp",
+    "char_inventory",
+    "char_jobs",
+    "char_look",
+    "char_points",
+    "char_profile",
+    "char_skills",
+    "char_stats",
+    "char_storage",
+    "char_titles",
+    "char_unlocks",
+    "char_vars",
+];
+
+/// The stores on the server database.
+pub struct MySqlStore {
+    pool: Pool,
+}
+
+impl MySqlStore {
+    pub fn new(pool: Pool) -> MySqlStore {
+        MySqlStore { pool }
+    }
+}
+
+#[async_trait]
+impl AccountStore for MySqlStore {
+    async fn find_account(&self, login: &str) -> Result<Option<Account>> {
+        let account: Option<(u32, u32, String)> =
+            r#"SELECT accounts.id,accounts.status,accounts.password
+            FROM accounts
+            WHERE accounts.login = :login"#
+                .with(params! {
+                    login
+                })
+                .first(&self.pool)
+                .await?;
+
+        Ok(account.map(|(acc_id, status, password)| Account {
+            acc_id,
+            status,
+            password,
+        }))
+    }
+
+    async fn create_account(
+        &self,
+        login: &str,
+        hash: &str,
+    ) -> Result<Option<u32>> {
+        let mut tx = self.pool.start_transaction(Default::default()).await?;
+
+        // Locking the highest id makes concurrent creations wait for each
+        // other, so they neither share an id nor miss each other's login.
+        let max_id: Option<Option<u32>> =
+            "SELECT MAX(accounts.id) FROM accounts FOR UPDATE"
+                .first(&mut tx)
+                .await?;
+
+        let taken: Option<u32> = r#"SELECT accounts.id FROM accounts
+            WHERE accounts.login = :login FOR UPDATE"#
+            .with(params! {
+                login
+            })
+            .first(&mut tx)
+            .await?;
+        if taken.is_some() {
+            return Ok(None);
+        }
+
+        let acc_id = max_id
+            .flatten()
+            .map_or(ACCOUNT_ID_MIN, |id| id + 1)
+            .max(ACCOUNT_ID_MIN);
+
+        r#"INSERT INTO accounts(id, login, password, timecreate,
+            timelastmodify, status, priv)
+            VALUES(:acc_id, :login, :hash, NOW(), NULL,
+            :status, :privilege)"#
+            .with(params! {
+                acc_id,
+                login,
+                hash,
+                "status" => ACCOUNT_STATUS_CODE_NORMAL,
+                "privilege" => ACCOUNT_PRIVILEGE_CODE_USER,
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(acc_id))
+    }
+
+    async fn set_password(&self, acc_id: u32, hash: &str) -> Result<()> {
+        r#"UPDATE accounts SET
+            accounts.password = :hash,
+            accounts.timelastmodify = NULL
+            WHERE accounts.id = :acc_id"#
+            .with(params! {
+                hash, acc_id
+            })
+            .ignore(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn touch_account(&self, acc_id: u32) -> Result<()> {
+        r#"UPDATE accounts SET
+            accounts.timelastmodify = NULL
+            WHERE accounts.id = :acc_id"#
+            .with(params! {
+                acc_id
+            })
+            .ignore(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn is_gm_account(&self, acc_id: u32) -> Result<bool> {
+        let is_gm: Option<bool> = r#"SELECT accounts.priv & :admin > 0
+            OR EXISTS(SELECT 1 FROM chars
+                WHERE chars.accid = accounts.id AND chars.gmlevel > 0
+                AND chars.deleted IS NULL)
+            FROM accounts
+            WHERE accounts.id = :acc_id"#
+            .with(params! {
+                "admin" => ACCOUNT_PRIVILEGE_CODE_ADMIN,
+                acc_id,
+            })
+            .first(&self.pool)
+            .await?;
+
+        Ok(is_gm.unwrap_or(false))
+    }
+
+    async fn ban_account(
+        &self,
+        acc_id: u32,
+        reason: &str,
+        until: Option<SystemTime>,
+    ) -> Result<()> {
+        let mut tx = self.pool.start_transaction(Default::default()).await?;
+
+        match until {
+            Some(until) => {
+                let until = until.duration_since(UNIX_EPOCH)?.as_secs();
+
+                r#"REPLACE INTO accounts_banned(accid, timebann, timeunbann,
+                    banncomment)
+                    VALUES(:acc_id, NOW(), FROM_UNIXTIME(:until), :reason)"#
+                    .with(params! {
+                        acc_id, until, reason
+                    })
+                    .ignore(&mut tx)
+                    .await?;
+            }
+            None => {
+                r#"REPLACE INTO accounts_banned(accid, timebann, timeunbann,
+                    banncomment)
+                    VALUES(:acc_id, NOW(), :until, :reason)"#
+                    .with(params! {
+                        acc_id,
+                        "until" => PERMANENT_BAN_END,
+                        reason,
+                    })
+                    .ignore(&mut tx)
+                    .await?;
+            }
+        }
+
+        r#"UPDATE accounts SET
+            accounts.status = :status
+            WHERE accounts.id = :acc_id"#
+            .with(params! {
+                "status" => ACCOUNT_STATUS_CODE_BANNED,
+                acc_id,
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn unban_account(&self, acc_id: u32) -> Result<()> {
+        let mut tx = self.pool.start_transaction(Default::default()).await?;
+
+        "DELETE FROM accounts_banned WHERE accid = :acc_id"
+            .with(params! {
+                acc_id
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        r#"UPDATE accounts SET
+            accounts.status = :status
+            WHERE accounts.id = :acc_id
+            AND accounts.status & :banned"#
+            .with(params! {
+                "status" => ACCOUNT_STATUS_CODE_NORMAL,
+                "banned" => ACCOUNT_STATUS_CODE_BANNED,
+                acc_id,
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn ban_active(&self, acc_id: u32) -> Result<Option<bool>> {
+        let active = r#"SELECT accounts_banned.timeunbann > NOW()
+            FROM accounts_banned
+            WHERE accounts_banned.accid = :acc_id"#
+            .with(params! {
+                acc_id
+            })
+            .first(&self.pool)
+            .await?;
+
+        Ok(active)
+    }
+
+    async fn game_session(&self, acc_id: u32) -> Result<Option<GameSession>> {
+        let session: Option<(u32, Vec<u8>, u32, u16, u32, bool)> =
+            r#"SELECT charid, session_key, server_addr, server_port,
+            client_addr, version_mismatch
+            FROM accounts_sessions
+            WHERE accid = :acc_id"#
+                .with(params! {
+                    acc_id
+                })
+                .first(&self.pool)
+                .await?;
+
+        Ok(session.map(
+            |(
+                char_id,
+                session_key,
+                server_addr,
+                server_port,
+                client_addr,
+                version_mismatch,
+            )| GameSession {
+                acc_id,
+                char_id,
+                session_key,
+                server: SocketAddrV4::new(
+                    Ipv4Addr::from(server_addr),
+                    server_port,
+                ),
+                client_addr,
+                version_mismatch,
+            },
+        ))
+    }
+
+    async fn game_sessions_from(
+        &self,
+        client_addr: Ipv4Addr,
+    ) -> Result<Vec<u32>> {
+        let accounts = r#"SELECT accid
+            FROM accounts_sessions
+            WHERE client_addr = :client_addr"#
+            .with(params! {
+                "client_addr" => u32::from(client_addr),
+            })
+            .fetch(&self.pool)
+            .await?;
+
+        Ok(accounts)
+    }
+
+    async fn start_game_session(&self, session: &GameSession) -> Result<()> {
+        r#"REPLACE INTO accounts_sessions(accid, charid, session_key,
+            server_addr, server_port, client_addr, version_mismatch)
+            VALUES(:acc_id, :char_id, :session_key, :server_addr,
+            :server_port, :client_addr, :version_mismatch)"#
+            .with(params! {
+                "acc_id" => session.acc_id,
+                "char_id" => session.char_id,
+                "session_key" => &session.session_key,
+                "server_addr" => u32::from(*session.server.ip()),
+                "server_port" => session.server.port(),
+                "client_addr" => session.client_addr,
+                "version_mismatch" => session.version_mismatch,
+            })
+            .ignore(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn end_game_session(&self, acc_id: u32) -> Result<()> {
+        "DELETE FROM accounts_sessions WHERE accid = :acc_id"
+            .with(params! {
+                acc_id
+            })
+            .ignore(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CharacterStore for MySqlStore {
+    async fn list_characters(
+        &self,
+        acc_id: u32,
+    ) -> Result<Vec<CharacterEntry>> {
+        let rows: Vec<Row> = r#"SELECT chars.charid, chars.charname,
+            IF(chars.pos_zone = 0, chars.pos_prevzone, chars.pos_zone) AS zone,
+            char_stats.mjob, char_stats.mlvl,
+            char_look.race, char_look.face, char_look.size,
+            char_look.head, char_look.body, char_look.hands, char_look.legs,
+            char_look.feet, char_look.main, char_look.sub
+            FROM chars
+            JOIN char_stats ON char_stats.charid = chars.charid
+            JOIN char_look ON char_look.charid = chars.charid
+            WHERE chars.accid = :acc_id AND chars.deleted IS NULL
+            ORDER BY chars.charid
+            LIMIT :limit"#
+            .with(params! {
+                acc_id,
+                "limit" => characters::MAX_CHARACTERS as u32,
+            })
+            .fetch(&self.pool)
+            .await?;
+
+        rows.into_iter().map(character_from_row).collect()
+    }
+
+    async fn name_taken(&self, name: &str) -> Result<bool> {
+        let existing: Option<u32> = r#"SELECT charid
+            FROM chars
+            WHERE charname = :name"#
+            .with(params! {
+                name
+            })
+            .first(&self.pool)
+            .await?;
+
+        Ok(existing.is_some())
+    }
+
+    async fn is_mob_or_npc_name(&self, name: &str) -> Result<bool> {
+        let exists: Option<bool> = r#"SELECT EXISTS(
+            SELECT 1 FROM npc_list
+            WHERE REPLACE(REPLACE(UPPER(npc_list.name), '_', ''), ' ', '')
+                = UPPER(:name)
+            UNION ALL
+            SELECT 1 FROM mob_pools
+            WHERE REPLACE(REPLACE(UPPER(mob_pools.name), '_', ''), ' ', '')
+                = UPPER(:name))"#
+            .with(params! {
+                name
+            })
+            .first(&self.pool)
+            .await?;
+
+        Ok(exists.unwrap_or(false))
+    }
+
+    async fn create_character(
+        &self,
+        config: &StartConfig,
+        acc_id: u32,
+        character: &NewCharacter,
+    ) -> Result<u32> {
+        let Some(job) = character.starting_job() else {
+            bail!("Invalid starting job {}", character.main_job);
+        };
+
+        let mut tx = self.pool.start_transaction(Default::default()).await?;
+
+        let max_id: Option<Option<u32>> =
+            "SELECT MAX(chars.charid) FROM chars FOR UPDATE"
+                .first(&mut tx)
+                .await?;
+        let char_id = max_id.flatten().map_or(1, |id| id + 1);
+
+        r#"INSERT INTO chars(charid, accid, charname, pos_zone, nation,
+            keyitems)
+            VALUES(:char_id, :acc_id, :name, :zone, :nation, :keyitems)"#
+            .with(params! {
+                char_id,
+                acc_id,
+                "name" => &character.name,
+                "zone" => character.nation.start_zone(char_id),
+                "nation" => character.nation as u8,
+                "keyitems" => config.all_maps.then(characters::map_key_items),
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        r#"INSERT INTO char_look(charid, face, race, size)
+            VALUES(:char_id, :face, :race, :size)"#
+            .with(params! {
+                char_id,
+                "face" => character.face,
+                "race" => character.race,
+                "size" => character.size,
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        r#"INSERT INTO char_stats(charid, mjob)
+            VALUES(:char_id, :main_job)"#
+            .with(params! {
+                char_id,
+                "main_job" => character.main_job,
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        format!(
+            "INSERT INTO char_jobs(charid, unlocked, {}) \
+            VALUES(:char_id, :unlocked, 1)",
+            job
+        )
+        .with(params! {
+            char_id,
+            "unlocked" => config.unlocked_jobs(character.main_job)?,
+        })
+        .ignore(&mut tx)
+        .await?;
+
+        for table in ["char_exp", "char_points", "char_profile"] {
+            format!("INSERT INTO {}(charid) VALUES(:char_id)", table)
+                .with(params! {
+                    char_id
+                })
+                .ignore(&mut tx)
+                .await?;
+        }
+
+        let outposts = config.outpost_warps.regions();
+        r#"INSERT INTO char_unlocks(charid, outpost_sandy, outpost_bastok,
+            outpost_windy)
+            VALUES(:char_id, :outposts, :outposts, :outposts)"#
+            .with(params! {
+                char_id,
+                outposts,
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        r#"INSERT INTO char_storage(charid, inventory, satchel)
+            VALUES(:char_id, :inventory, :inventory)"#
+            .with(params! {
+                char_id,
+                "inventory" => config.inventory_size,
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        if config.gil > 0 {
+            r#"INSERT INTO char_inventory(charid, location, slot, itemId,
+                quantity)
+                VALUES(:char_id, 0, 0, :gil_item, :gil)"#
+                .with(params! {
+                    char_id,
+                    "gil_item" => GIL_ITEM_ID,
+                    "gil" => config.gil,
+                })
+                .ignore(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(char_id)
+    }
+
+    async fn delete_character(
+        &self,
+        acc_id: u32,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>> {
+        let mut tx = self.pool.start_transaction(Default::default()).await?;
+
+        if let Err(err) =
+            check_deletable(&mut tx, char_id, Some(acc_id)).await?
+        {
+            return Ok(Err(err));
+        }
+
+        r#"UPDATE chars SET deleted = NOW()
+            WHERE charid = :char_id"#
+            .with(params! {
+                char_id
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Ok(()))
+    }
+
+    async fn restore_character(
+        &self,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>> {
+        let mut conn = self.pool.get_conn().await?;
+
+        r#"UPDATE chars SET deleted = NULL
+            WHERE charid = :char_id AND deleted IS NOT NULL"#
+            .with(params! {
+                char_id
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        if conn.affected_rows() == 0 {
+            return Ok(Err(DeletionError::NotFound));
+        }
+
+        Ok(Ok(()))
+    }
+
+    async fn purge_character(
+        &self,
+        char_id: u32,
+    ) -> Result<Result<(), DeletionError>> {
+        let mut tx = self.pool.start_transaction(Default::default()).await?;
+
+        let deleted: Option<u32> = r#"SELECT charid FROM chars
+            WHERE charid = :char_id AND deleted IS NOT NULL
+            FOR UPDATE"#
+            .with(params! {
+                char_id
+            })
+            .first(&mut tx)
+            .await?;
+
+        if deleted.is_none() {
+            return Ok(Err(DeletionError::NotFound));
+        }
+
+        if let Err(err) = check_deletable(&mut tx, char_id, None).await? {
+            return Ok(Err(err));
+        }
+
+        for table in CHARACTER_TABLES {
+            format!("DELETE FROM {} WHERE charid = :char_id", table)
+                .with(params! {
+                    char_id
+                })
+                .ignore(&mut tx)
+                .await?;
+        }
+
+        "DELETE FROM chars WHERE charid = :char_id"
+            .with(params! {
+                char_id
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Ok(()))
+    }
+
+    async fn world_entry(
+        &self,
+        acc_id: u32,
+        char_id: u32,
+    ) -> Result<Option<WorldEntry>> {
+        let zone: Option<(String, u16, String, u8)> =
+            r#"SELECT zone_settings.zoneip, zone_settings.zoneport,
+            chars.charname, chars.gmlevel
+            FROM chars JOIN zone_settings
+            ON zone_settings.zoneid =
+                IF(chars.pos_zone = 0, chars.pos_prevzone, chars.pos_zone)
+            WHERE chars.charid = :char_id AND chars.accid = :acc_id
+            AND chars.deleted IS NULL"#
+                .with(params! {
+                    char_id, acc_id
+                })
+                .first(&self.pool)
+                .await?;
+
+        let Some((zone_ip, zone_port, char_name, gm_level)) = zone else {
+            return Ok(None);
+        };
+
+        Ok(Some(WorldEntry {
+            char_name,
+            gm_level,
+            zone_server: SocketAddrV4::new(zone_ip.parse()?, zone_port),
+        }))
+    }
+}
+
+fn character_from_row(mut row: Row) -> Result<CharacterEntry> {
+    fn take<T: FromValue>(row: &mut Row, column: &str) -> Result<T> {
+        match row.take_opt(column) {
+            Some(Ok(value)) => Ok(value),
+            _ => bail!("Could not read column {} of character", column),
+        }
+    }
+
+    let mut look = [0; 7];
+    for (slot, column) in look
+        .iter_mut()
+        .zip(["head", "body", "hands", "legs", "feet", "main", "sub"])
+    {
+        *slot = take(&mut row, column)?;
+    }
+
+    Ok(CharacterEntry {
+        char_id: take(&mut row, "charid")?,
+        name: take(&mut row, "charname")?,
+        zone: take(&mut row, "zone")?,
+        main_job: take(&mut row, "mjob")?,
+        main_job_level: take(&mut row, "mlvl")?,
+        race: take(&mut row, "race")?,
+        face: take(&mut row, "face")?,
+        size: take(&mut row, "size")?,
+        look,
+    })
+}
+
+/// Checks that the character exists, belongs to `acc_id` if given, and is
+/// not in game. Locks its row until the transaction ends.
+async fn check_deletable(
+    tx: &mut Transaction<'_>,
+    char_id: u32,
+    acc_id: Option<u32>,
+) -> Result<Result<(), DeletionError>> {
+    let owner: Option<u32> = r#"SELECT accid FROM chars
+        WHERE charid = :char_id
+        AND (deleted IS NULL OR :acc_id IS NULL)
+        FOR UPDATE"#
+        .with(params! {
+            char_id, acc_id
+        })
+        .first(&mut *tx)
+        .await?;
+
+    match (owner, acc_id) {
+        (None, _) => return Ok(Err(DeletionError::NotFound)),
+        (Some(owner), Some(acc_id)) if owner != acc_id => {
+            return Ok(Err(DeletionError::NotFound))
+        }
+        _ => {}
+    }
+
+    let online: Option<u32> = r#"SELECT charid FROM accounts_sessions
+        WHERE charid = :char_id"#
+        .with(params! {
+            char_id
+        })
+        .first(&mut *tx)
+        .await?;
+
+    if online.is_some() {
+        return Ok(Err(DeletionError::Online));
+    }
+
+    Ok(Ok(()))
+}
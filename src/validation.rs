@@ -0,0 +1,183 @@
+use thiserror::Error;
+
+pub const ACCOUNT_NAME_MIN_LEN: usize = 3;
+pub const ACCOUNT_NAME_MAX_LEN: usize = 16;
+pub const ACCOUNT_PASSWORD_MIN_LEN: usize = 6;
+pub const ACCOUNT_PASSWORD_MAX_LEN: usize = 16;
+pub const CHAR_NAME_MIN_LEN: usize = 3;
+pub const CHAR_NAME_MAX_LEN: usize = 15;
+
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountError {
+    #[error("account name must be {ACCOUNT_NAME_MIN_LEN} to {ACCOUNT_NAME_MAX_LEN} characters")]
+    NameLength,
+    #[error("account name may only contain letters and digits")]
+    NameCharset,
+    #[error("account name contains a banned word")]
+    NameBanned,
+    #[error("password must be {ACCOUNT_PASSWORD_MIN_LEN} to {ACCOUNT_PASSWORD_MAX_LEN} characters")]
+    PasswordLength,
+    #[error("password may only contain printable characters")]
+    PasswordCharset,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CharNameError {
+    #[error("character name has the wrong length")]
+    Length,
+    #[error("character name may only contain letters")]
+    Charset,
+    #[error("character name contains a banned word")]
+    Banned,
+    #[error("character name belongs to a mob or NPC")]
+    MobOrNpc,
+    #[error("character name is already taken")]
+    Taken,
+}
+
+/// Checks the name and password of an account about to be created.
+pub fn validate_account(
+    name: &str,
+    password: &str,
+    banned_words: &[String],
+) -> Result<(), AccountError> {
+    if !(ACCOUNT_NAME_MIN_LEN..=ACCOUNT_NAME_MAX_LEN).contains(&name.len()) {
+        return Err(AccountError::NameLength);
+    }
+
+    if !name.bytes().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(AccountError::NameCharset);
+    }
+
+    if contains_banned_word(name, banned_words) {
+        return Err(AccountError::NameBanned);
+    }
+
+    if !(ACCOUNT_PASSWORD_MIN_LEN..=ACCOUNT_PASSWORD_MAX_LEN)
+        .contains(&password.len())
+    {
+        return Err(AccountError::PasswordLength);
+    }
+
+    if !password.bytes().all(|c| c.is_ascii_graphic()) {
+        return Err(AccountError::PasswordCharset);
+    }
+
+    Ok(())
+}
+
+/// Checks the parts of a character name that do not depend on the database.
+pub fn validate_char_name(
+    name: &str,
+    banned_words: &[String],
+) -> Result<(), CharNameError> {
+    if !(CHAR_NAME_MIN_LEN..=CHAR_NAME_MAX_LEN).contains(&name.len()) {
+        return Err(CharNameError::Length);
+    }
+
+    if !name.bytes().all(|c| c.is_ascii_alphabetic()) {
+        return Err(CharNameError::Charset);
+    }
+
+    if contains_banned_word(name, banned_words) {
+        return Err(CharNameError::Banned);
+    }
+
+    Ok(())
+}
+
+/// Case-insensitive substring match against `BANNED_WORDS_LIST`.
+pub fn contains_banned_word(name: &str, banned_words: &[String]) -> bool {
+    let name = name.to_lowercase();
+
+    banned_words
+        .iter()
+        .filter(|word| !word.is_empty())
+        .any(|word| name.contains(&word.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banned() -> Vec<String> {
+        vec!["badword".to_owned()]
+    }
+
+    #[test]
+    fn it_accepts_valid_accounts() {
+        assert_eq!(validate_account("Player1", "hunter22", &banned()), Ok(()));
+    }
+
+    #[test]
+    fn it_rejects_invalid_names() {
+        assert_eq!(
+            validate_account("ab", "hunter22", &banned()),
+            Err(AccountError::NameLength)
+        );
+        assert_eq!(
+            validate_account("abcdefghijklmnopq", "hunter22", &banned()),
+            Err(AccountError::NameLength)
+        );
+        assert_eq!(
+            validate_account("bad name", "hunter22", &banned()),
+            Err(AccountError::NameCharset)
+        );
+        assert_eq!(
+            validate_account("ImBADWORDlol", "hunter22", &banned()),
+            Err(AccountError::NameBanned)
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_passwords() {
+        assert_eq!(
+            validate_account("Player1", "short", &banned()),
+            Err(AccountError::PasswordLength)
+        );
+        assert_eq!(
+            validate_account("Player1", "with space", &banned()),
+            Err(AccountError::PasswordCharset)
+        );
+    }
+
+    #[test]
+    fn it_accepts_valid_char_names() {
+        assert_eq!(validate_char_name("Shantotto", &banned()), Ok(()));
+        assert_eq!(validate_char_name("Ayn", &banned()), Ok(()));
+        assert_eq!(validate_char_name("Abcdefghijklmno", &banned()), Ok(()));
+    }
+
+    #[test]
+    fn it_rejects_invalid_char_names() {
+        assert_eq!(
+            validate_char_name("Ab", &banned()),
+            Err(CharNameError::Length)
+        );
+        assert_eq!(
+            validate_char_name("Abcdefghijklmnop", &banned()),
+            Err(CharNameError::Length)
+        );
+        assert_eq!(
+            validate_char_name("Player1", &banned()),
+            Err(CharNameError::Charset)
+        );
+        assert_eq!(
+            validate_char_name("Two Words", &banned()),
+            Err(CharNameError::Charset)
+        );
+        assert_eq!(
+            validate_char_name("Ñame", &banned()),
+            Err(CharNameError::Charset)
+        );
+        assert_eq!(
+            validate_char_name("XxBadWordxX", &banned()),
+            Err(CharNameError::Banned)
+        );
+    }
+
+    #[test]
+    fn it_ignores_empty_banned_words() {
+        assert!(!contains_banned_word("Player", &["".to_owned()]));
+    }
+}